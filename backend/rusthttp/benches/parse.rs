@@ -0,0 +1,158 @@
+//! Request parser throughput, compared against the original whole-buffer parser.
+//!
+//! Run with `cargo bench --bench parse --manifest-path backend/rusthttp/Cargo.toml`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use rusthttp::{RequestParser, parse_http_request};
+
+/// Delivers the input in fixed-size pieces, like a socket under load.
+struct Chunked<'a> {
+    data: &'a [u8],
+    chunk: usize,
+}
+
+impl Read for Chunked<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.data.len().min(self.chunk).min(buf.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+/// The parser as it was before the incremental rewrite, kept verbatim for comparison.
+mod legacy {
+    use super::*;
+
+    #[allow(dead_code)]
+    pub struct Request {
+        pub method: String,
+        pub path: String,
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    pub fn parse_http_request<R: Read>(stream: &mut R) -> io::Result<Request> {
+        fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+            haystack.windows(needle.len()).position(|w| w == needle)
+        }
+
+        let mut buf = Vec::<u8>::new();
+        let mut tmp = [0u8; 4096];
+
+        loop {
+            let n = stream.read(&mut tmp)?;
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&tmp[..n]);
+            if find_subsequence(&buf, b"\r\n\r\n").is_some() {
+                break;
+            }
+            if buf.len() > 1024 * 1024 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "headers too large"));
+            }
+        }
+
+        let header_end = find_subsequence(&buf, b"\r\n\r\n").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP request"))? + 4;
+
+        let header_bytes = &buf[..header_end];
+        let header_text = String::from_utf8_lossy(header_bytes);
+        let mut lines = header_text.split("\r\n");
+
+        let request_line = lines.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing request line"))?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let path = parts.next().unwrap_or("").to_string();
+
+        if method.is_empty() || path.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request line"));
+        }
+
+        let mut headers = HashMap::<String, String>::new();
+        for line in lines {
+            if line.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                headers.insert(k.trim().to_ascii_lowercase(), v.trim().to_string());
+            }
+        }
+
+        let content_length = headers.get("content-length").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+
+        let mut body = Vec::<u8>::new();
+        let already = buf.len().saturating_sub(header_end);
+        if already > 0 {
+            body.extend_from_slice(&buf[header_end..]);
+        }
+
+        while body.len() < content_length {
+            let n = stream.read(&mut tmp)?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&tmp[..n]);
+            if body.len() > 1024 * 1024 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "body too large"));
+            }
+        }
+
+        Ok(Request { method, path, headers, body })
+    }
+}
+
+fn request_with_headers(count: usize, value_len: usize) -> Vec<u8> {
+    let mut req = b"POST /api/notes HTTP/1.1\r\nHost: localhost:8080\r\nContent-Type: application/json\r\n".to_vec();
+    for i in 0..count {
+        req.extend_from_slice(format!("X-Header-{}: {}\r\n", i, "v".repeat(value_len)).as_bytes());
+    }
+    req.extend_from_slice(b"Content-Length: 17\r\n\r\n{\"content\":\"hi\"}\n");
+    req
+}
+
+fn bench(name: &str, input: &[u8], chunk: usize, mut f: impl FnMut(&mut Chunked<'_>)) {
+    // Warm up, then run for a fixed wall-clock budget.
+    for _ in 0..10 {
+        f(&mut Chunked { data: input, chunk });
+    }
+    let budget = Duration::from_millis(500);
+    let start = Instant::now();
+    let mut iters = 0u64;
+    while start.elapsed() < budget {
+        f(&mut Chunked { data: input, chunk });
+        iters += 1;
+    }
+    let elapsed = start.elapsed();
+    let per_iter = elapsed / iters as u32;
+    let mib_s = (input.len() as f64 * iters as f64) / elapsed.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{:<44} {:>10.2?}/req {:>10.1} MiB/s", name, per_iter, mib_s);
+}
+
+fn main() {
+    let cases = [("small (8 headers)", request_with_headers(8, 16), 4096), ("many headers (200)", request_with_headers(200, 32), 512), ("large head (64 KiB)", request_with_headers(64, 1000), 1024)];
+
+    for (label, input, chunk) in &cases {
+        println!("{} — {} bytes in {}-byte reads", label, input.len(), chunk);
+        bench("  legacy parse_http_request", input, *chunk, |r| {
+            black_box(legacy::parse_http_request(r).unwrap());
+        });
+        bench("  parse_http_request", input, *chunk, |r| {
+            black_box(parse_http_request(r).unwrap());
+        });
+        let mut parser = RequestParser::new();
+        bench("  RequestParser::read_head (borrowed, reused)", input, *chunk, |r| {
+            let raw = parser.read_head(r).unwrap().unwrap();
+            black_box((raw.method(), raw.path(), raw.content_length()));
+            parser.consume();
+            // Drop the buffered body so the next iteration starts a fresh request.
+            let len = parser.buffered_body().len();
+            let _ = parser.read_body(&mut io::empty(), len, usize::MAX);
+        });
+    }
+}
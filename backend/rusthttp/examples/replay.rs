@@ -0,0 +1,49 @@
+//! Replay a recording made with `rusthttp::Recorder` against a running server and report the
+//! responses that changed:
+//!
+//! ```text
+//! cargo run --example replay -- requests.jsonl 127.0.0.1:8080 --ignore-header etag
+//! ```
+//!
+//! Exits with status 1 if any response differs.
+
+use std::io::BufReader;
+use std::process::ExitCode;
+
+use rusthttp::record::{Exchange, Replay};
+
+fn main() -> std::io::Result<ExitCode> {
+    let mut args = std::env::args().skip(1);
+    let (Some(recording), Some(addr)) = (args.next(), args.next()) else {
+        eprintln!("usage: replay <recording.jsonl> <host:port> [--ignore-header <name>]...");
+        return Ok(ExitCode::from(2));
+    };
+    let mut replay = Replay::new();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--ignore-header", Some(name)) => replay = replay.ignore_header(&name),
+            _ => {
+                eprintln!("unknown argument: {}", arg);
+                return Ok(ExitCode::from(2));
+            }
+        }
+    }
+
+    let exchanges = Exchange::read_all(BufReader::new(std::fs::File::open(&recording)?))?;
+    let mut changed = 0;
+    for exchange in &exchanges {
+        let differences = replay.against_server(&addr, exchange)?;
+        let request = &exchange.request;
+        if differences.is_empty() {
+            println!("ok      {} {}", request.method, request.path);
+            continue;
+        }
+        changed += 1;
+        println!("CHANGED {} {} (recorded as request {} at {})", request.method, request.path, exchange.request_id, exchange.time);
+        for difference in differences {
+            println!("        {}", difference);
+        }
+    }
+    println!("{} of {} responses changed", changed, exchanges.len());
+    Ok(if changed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
@@ -0,0 +1,24 @@
+//! Serves HTTPS on 127.0.0.1:8443 using the self-signed certificate in `examples/certs`.
+//!
+//! cargo run --example tls_server --features tls --manifest-path backend/rusthttp/Cargo.toml
+
+use std::time::Duration;
+
+use rusthttp::{Method, Router, Server, TlsConfig};
+
+fn main() -> std::io::Result<()> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/certs");
+    let tls = TlsConfig::from_pem_files(format!("{}/localhost-cert.pem", dir), format!("{}/localhost-key.pem", dir))?;
+    // Replacing the PEM files is picked up without a restart.
+    tls.watch(Duration::from_secs(5));
+
+    let mut router = Router::new();
+    router.add_route(Method::Get, "/health", |req, stream| {
+        let tls = req.connection.tls.as_ref();
+        let body = format!("ok over {} ({})\n", tls.map_or("plain HTTP", |t| t.version.as_str()), tls.and_then(|t| t.alpn_protocol.as_deref()).unwrap_or("no ALPN"));
+        rusthttp::write_response(stream, 200, "OK", "text/plain", body.as_bytes())
+    });
+
+    println!("Try: curl --cacert {}/localhost-cert.pem https://localhost:8443/health", dir);
+    Server::new(router).tls(tls).serve("127.0.0.1:8443")
+}
@@ -0,0 +1,351 @@
+//! One line per request, in Common/Combined Log Format or JSON lines.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::date;
+use crate::json;
+use crate::request::Request;
+
+/// Everything known about a finished request.
+pub struct AccessLogEntry<'a> {
+    /// The request, or `None` if its head could not be parsed.
+    pub request: Option<&'a Request>,
+    /// The request's id, also assigned when its head could not be parsed.
+    pub request_id: &'a str,
+    pub peer_addr: Option<SocketAddr>,
+    /// Final status code sent, or 0 if no response was written.
+    pub status: u16,
+    /// Response body bytes written.
+    pub bytes: u64,
+    /// Time from the request head arriving to the response being written.
+    pub latency: Duration,
+    /// When the request head arrived.
+    pub time: SystemTime,
+}
+
+impl AccessLogEntry<'_> {
+    pub fn method(&self) -> &str {
+        self.request.map_or("-", |r| r.method.as_str())
+    }
+
+    pub fn path(&self) -> &str {
+        self.request.map_or("-", |r| r.path.as_str())
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.request.and_then(|r| r.header(name))
+    }
+
+    /// The client address: resolved client IP if known, else the peer IP.
+    fn client(&self) -> String {
+        match (self.request.and_then(|r| r.client_ip), self.peer_addr) {
+            (Some(ip), _) => ip.to_string(),
+            (None, Some(peer)) => peer.ip().to_string(),
+            (None, None) => "-".to_string(),
+        }
+    }
+
+    fn request_line(&self) -> String {
+        match self.request {
+            Some(r) => escape(&format!("{} {} {}", r.method, r.path, r.version)),
+            None => "-".to_string(),
+        }
+    }
+}
+
+/// `value` made safe to quote in a log line: `"` and `\` are backslash-escaped, and control
+/// characters become `\xHH` like in nginx logs, so a header cannot forge a line or field.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_ascii_control() => out.push_str(&format!("\\x{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Receives one call per finished request. Implemented for closures.
+pub trait AccessLogger: Send + Sync {
+    fn log(&self, entry: &AccessLogEntry<'_>);
+}
+
+impl<F> AccessLogger for F
+where
+    F: Fn(&AccessLogEntry<'_>) + Send + Sync,
+{
+    fn log(&self, entry: &AccessLogEntry<'_>) {
+        self(entry)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `host ident authuser [date] "request" status bytes`
+    Common,
+    /// Common plus `"referer" "user-agent"`.
+    Combined,
+    /// One JSON object per line, including latency, peer address and request id.
+    Json,
+}
+
+impl LogFormat {
+    /// Render `entry` as a single line, without the trailing newline.
+    pub fn format(&self, entry: &AccessLogEntry<'_>) -> String {
+        let bytes = if entry.bytes == 0 { "-".to_string() } else { entry.bytes.to_string() };
+        let quoted = |v: Option<&str>| v.map_or_else(|| "-".to_string(), escape);
+        match self {
+            LogFormat::Common => format!("{} - - [{}] \"{}\" {} {}", entry.client(), date::format_clf(entry.time), entry.request_line(), entry.status, bytes),
+            LogFormat::Combined => format!(
+                "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"",
+                entry.client(),
+                date::format_clf(entry.time),
+                entry.request_line(),
+                entry.status,
+                bytes,
+                quoted(entry.header("referer")),
+                quoted(entry.header("user-agent"))
+            ),
+            LogFormat::Json => format!(
+                "{{\"time\":{},\"client\":{},\"peer\":{},\"method\":{},\"path\":{},\"status\":{},\"bytes\":{},\"latency_ms\":{:.3},\"request_id\":{},\"user_agent\":{}}}",
+                json::string(&date::format_rfc3339(entry.time)),
+                json::string(&entry.client()),
+                json::opt_string(entry.peer_addr.map(|a| a.to_string()).as_deref()),
+                json::string(entry.method()),
+                json::string(entry.path()),
+                entry.status,
+                entry.bytes,
+                entry.latency.as_secs_f64() * 1000.0,
+                json::string(entry.request_id),
+                json::opt_string(entry.header("user-agent")),
+            ),
+        }
+    }
+}
+
+/// The built-in logger: formats entries and writes them to stdout or a file.
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Sink,
+}
+
+enum Sink {
+    Stdout,
+    File(Mutex<RotatingFile>),
+}
+
+impl AccessLog {
+    pub fn stdout(format: LogFormat) -> Self {
+        Self { format, sink: Sink::Stdout }
+    }
+
+    /// Append to `file`, which rotates itself as configured.
+    pub fn file(format: LogFormat, file: RotatingFile) -> Self {
+        Self { format, sink: Sink::File(Mutex::new(file)) }
+    }
+}
+
+impl AccessLogger for AccessLog {
+    fn log(&self, entry: &AccessLogEntry<'_>) {
+        let mut line = self.format.format(entry);
+        line.push('\n');
+        let result = match &self.sink {
+            Sink::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Sink::File(file) => file.lock().unwrap().write_all(line.as_bytes()),
+        };
+        if let Err(e) = result {
+            eprintln!("failed to write access log: {}", e);
+        }
+    }
+}
+
+/// A log file that is rotated once it grows past a size limit.
+///
+/// On rotation `access.log` becomes `access.log.1`, `access.log.1` becomes `access.log.2`, and so
+/// on; the oldest file beyond `keep` is deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+    /// Permission bits for newly created files; `None` leaves them to the umask.
+    mode: Option<u32>,
+}
+
+impl RotatingFile {
+    pub fn new(path: impl AsRef<Path>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        Self::open(path.as_ref(), max_bytes, keep, None)
+    }
+
+    /// Like [`new`](Self::new), but files are created with permission bits `mode`, e.g. `0o600`
+    /// for logs holding request data only the server's user should read. An existing file is
+    /// changed to `mode` too. Ignored where files have no Unix permissions.
+    pub fn with_mode(path: impl AsRef<Path>, max_bytes: u64, keep: usize, mode: u32) -> io::Result<Self> {
+        let file = Self::open(path.as_ref(), max_bytes, keep, Some(mode))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        Ok(file)
+    }
+
+    fn open(path: &Path, max_bytes: u64, keep: usize, mode: Option<u32>) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = options(mode).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, max_bytes, keep, file, size, mode })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = options(self.mode).write(true).truncate(true).open(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = options(self.mode).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Options creating a file, with permission bits `mode` where the platform has them.
+fn options(mode: Option<u32>) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options
+}
+
+impl Write for RotatingFile {
+    /// Writes are never split across files, so call with whole lines.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use crate::testing::{connect_server, echo_router};
+
+    #[test]
+    fn access_log_records_status_bytes_and_client() {
+        use std::io::{Read, Write};
+        use std::sync::{Arc, Mutex};
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&lines);
+        let logger = move |entry: &AccessLogEntry<'_>| sink.lock().unwrap().push((LogFormat::Common.format(entry), LogFormat::Json.format(entry)));
+
+        let mut client = connect_server(Server::new(echo_router(64)).access_log(logger));
+        client.write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\nX-Request-Id: abc\r\n\r\nhello").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+
+        // The entry is logged after the response is flushed; give the server thread a moment.
+        for _ in 0..100 {
+            if !lines.lock().unwrap().is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let (common, json) = lines.lock().unwrap().pop().expect("logged");
+        assert!(common.starts_with("127.0.0.1 - - ["), "{}", common);
+        assert!(common.ends_with("] \"POST /echo HTTP/1.1\" 200 5"), "{}", common);
+        assert!(json.contains("\"status\":200,\"bytes\":5,"), "{}", json);
+        assert!(json.contains("\"request_id\":\"abc\""), "{}", json);
+        assert!(resp.contains("\r\nX-Request-Id: abc\r\n\r\nhello"), "{}", resp);
+    }
+
+    #[test]
+    fn combined_format_escapes_quoted_fields() {
+        let mut req = Request::new("GET", "/a\"b");
+        req.headers.insert("user-agent".to_string(), "x\" \\ \n127.0.0.1 - - [forged]\t\u{7f}é".to_string());
+        let entry = AccessLogEntry { request: Some(&req), request_id: "", peer_addr: None, status: 200, bytes: 0, latency: Duration::ZERO, time: SystemTime::UNIX_EPOCH };
+        let line = LogFormat::Combined.format(&entry);
+        assert!(line.ends_with(r#"] "GET /a\"b HTTP/1.1" 200 - "-" "x\" \\ \x0A127.0.0.1 - - [forged]\x09\x7Fé""#), "{}", line);
+    }
+
+    #[test]
+    fn rotating_file_keeps_generations() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("rusthttp-rotate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("access.log");
+        let mut file = RotatingFile::new(&path, 10, 2).unwrap();
+        for line in ["first...\n", "second..\n", "third...\n", "fourth..\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth..\n");
+        assert_eq!(std::fs::read_to_string(dir.join("access.log.1")).unwrap(), "third...\n");
+        assert_eq!(std::fs::read_to_string(dir.join("access.log.2")).unwrap(), "second..\n");
+        assert!(!dir.join("access.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotating_file_creates_files_with_mode() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("rusthttp-rotate-mode-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("recording.jsonl");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let mut file = RotatingFile::with_mode(&path, 10, 1, 0o600).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        for line in ["first...\n", "second..\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&dir.join("recording.jsonl.1")), 0o600);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use crate::request::Request;
+
+/// Longest chunk-size or trailer line accepted in a chunked body.
+const MAX_LINE_BYTES: usize = 4096;
+
+/// How the end of a message body is determined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Exactly this many bytes follow the head.
+    Length(u64),
+    /// `Transfer-Encoding: chunked`.
+    Chunked,
+}
+
+impl Framing {
+    /// Determine body framing from request headers.
+    ///
+    /// Requests with neither `Content-Length` nor `Transfer-Encoding` have an empty body.
+    /// A request carrying both, or an unsupported transfer coding, is rejected.
+    pub fn from_request(req: &Request) -> io::Result<Framing> {
+        let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        match (req.header("transfer-encoding"), req.header("content-length")) {
+            (Some(_), Some(_)) => Err(bad("both Transfer-Encoding and Content-Length")),
+            (Some(te), None) => {
+                if te.rsplit(',').next().map(str::trim).is_some_and(|c| c.eq_ignore_ascii_case("chunked")) {
+                    Ok(Framing::Chunked)
+                } else {
+                    Err(bad("unsupported transfer coding"))
+                }
+            }
+            (None, Some(len)) => len.trim().parse::<u64>().map(Framing::Length).map_err(|_| bad("invalid Content-Length")),
+            (None, None) => Ok(Framing::Length(0)),
+        }
+    }
+}
+
+/// Error payload used when a body exceeds its route's limit.
+///
+/// Returned inside an [`io::Error`] of kind `InvalidData`; test for it with [`is_too_large`].
+#[derive(Debug)]
+pub struct BodyTooLarge {
+    pub limit: u64,
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "body exceeds limit of {} bytes", self.limit)
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+/// Whether `err` was caused by a body exceeding its limit.
+pub fn is_too_large(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|inner| inner.is::<BodyTooLarge>())
+}
+
+enum State {
+    /// Bytes left in a `Content-Length` body or in the current chunk.
+    Data(u64),
+    /// Expecting a chunk-size line.
+    ChunkSize,
+    /// Expecting the CRLF that ends a chunk's data.
+    ChunkEnd,
+    Done,
+}
+
+enum Source<'a> {
+    Borrowed(&'a mut dyn BufRead),
+    /// A decoder over the framed body; its end is the end of the body.
+    #[cfg(feature = "compression")]
+    Decoded(Box<dyn BufRead + 'a>),
+}
+
+/// A request body read incrementally from the connection.
+///
+/// Honors `Content-Length` and chunked framing, stops at the end of the body (never reading into
+/// a following request), and fails with [`BodyTooLarge`] once more than `limit` bytes arrive.
+pub struct Body<'a> {
+    inner: Source<'a>,
+    chunked: bool,
+    state: State,
+    limit: u64,
+    read: u64,
+}
+
+impl<'a> Body<'a> {
+    pub fn new(inner: &'a mut dyn BufRead, framing: Framing, limit: u64) -> Self {
+        let (chunked, state) = match framing {
+            Framing::Length(0) => (false, State::Done),
+            Framing::Length(n) => (false, State::Data(n)),
+            Framing::Chunked => (true, State::ChunkSize),
+        };
+        Self { inner: Source::Borrowed(inner), chunked, state, limit, read: 0 }
+    }
+
+    /// This body decoded with `coding`, with `limit` applying to the decoded bytes.
+    #[cfg(feature = "compression")]
+    pub(crate) fn decoded(self, coding: crate::encoding::Coding) -> Body<'a> {
+        let limit = self.limit;
+        let decoder = crate::encoding::Decoder::new(io::BufReader::new(self), coding);
+        Body { inner: Source::Decoded(Box::new(io::BufReader::new(decoder))), chunked: false, state: State::Data(u64::MAX), limit, read: 0 }
+    }
+
+    fn source(&mut self) -> &mut dyn BufRead {
+        match &mut self.inner {
+            Source::Borrowed(inner) => *inner,
+            #[cfg(feature = "compression")]
+            Source::Decoded(inner) => inner,
+        }
+    }
+
+    /// Number of body bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// Read the remaining body into a vector.
+    pub fn read_to_vec(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        let n = self.source().take(MAX_LINE_BYTES as u64).read_until(b'\n', &mut line)?;
+        if n == 0 || !line.ends_with(b"\n") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed chunked body"));
+        }
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        Ok(line)
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let line = self.read_line()?;
+        let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+        let size = std::str::from_utf8(size).ok().and_then(|s| u64::from_str_radix(s.trim(), 16).ok());
+        match size {
+            Some(0) => {
+                // Skip trailer fields up to the blank line that ends the message.
+                while !self.read_line()?.is_empty() {}
+                self.state = State::Done;
+            }
+            Some(n) => self.state = State::Data(n),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size")),
+        }
+        Ok(())
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::ChunkSize => self.next_chunk()?,
+                State::ChunkEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed chunked body"));
+                    }
+                    self.state = State::ChunkSize;
+                }
+                State::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let want = remaining.min(buf.len() as u64) as usize;
+                    let n = self.source().read(&mut buf[..want])?;
+                    #[cfg(feature = "compression")]
+                    if n == 0 && matches!(self.inner, Source::Decoded(_)) {
+                        self.state = State::Done;
+                        return Ok(0);
+                    }
+                    if n == 0 {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body ended early"));
+                    }
+                    self.read += n as u64;
+                    if self.read > self.limit {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, BodyTooLarge { limit: self.limit }));
+                    }
+                    let remaining = remaining - n as u64;
+                    self.state = match (remaining, self.chunked) {
+                        (0, true) => State::ChunkEnd,
+                        (0, false) => State::Done,
+                        (n, _) => State::Data(n),
+                    };
+                    return Ok(n);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router, parse_http_request, write_response};
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_chunked_body() {
+        let mut data = Cursor::new(b"POST /up HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: t\r\n\r\n".to_vec());
+        let req = parse_http_request(&mut data).expect("parse");
+        assert_eq!(req.body, b"hello world");
+    }
+
+    #[test]
+    fn streaming_route_enforces_limit() {
+        let mut router = Router::new();
+        router
+            .add_streaming_route(Method::Post, "/up", |_req, body, stream| {
+                let mut received = Vec::new();
+                match std::io::Read::read_to_end(body, &mut received) {
+                    Ok(_) => write_response(stream, 200, "OK", "text/plain", &received),
+                    Err(e) if is_too_large(&e) => write_response(stream, 413, "Payload Too Large", "text/plain", b""),
+                    Err(e) => Err(e),
+                }
+            })
+            .body_limit(4);
+
+        let mut req = Request::new("POST", "/up");
+        req.body = b"abc".to_vec();
+        let mut out = Vec::new();
+        router.handle(req, &mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).ends_with("abc"));
+
+        let mut req = Request::new("POST", "/up");
+        req.body = b"abcdef".to_vec();
+        let mut out = Vec::new();
+        router.handle(req, &mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains("413"));
+    }
+}
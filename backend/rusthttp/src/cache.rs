@@ -0,0 +1,321 @@
+//! An in-memory cache of rendered `GET` responses.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Cursor, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::client;
+use crate::middleware::{Middleware, Next};
+use crate::request::{Request, RequestParser};
+use crate::response::{InjectHeaders, Tee};
+
+/// Middleware caching `200 OK` responses to `GET` requests in memory, keyed by path and query
+/// plus the values of the configured [`vary`](Self::vary) headers.
+///
+/// Share one cache between the routes it wraps and the handlers that change their data, and
+/// [`invalidate`](Self::invalidate) after each write:
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use rusthttp::{Method, ResponseCache, Router, write_response};
+///
+/// let cache = Arc::new(ResponseCache::new(16 * 1024 * 1024).ttl(Duration::from_secs(30)).vary("authorization"));
+/// let mut router = Router::new();
+/// router.add_route(Method::Get, "/notes", |_req, stream| write_response(stream, 200, "OK", "application/json", b"[]")).wrap(Arc::clone(&cache));
+/// router.add_route(Method::Post, "/notes", move |_req, stream| {
+///     cache.invalidate("/notes");
+///     write_response(stream, 201, "Created", "application/json", b"{}")
+/// });
+/// ```
+///
+/// `Cache-Control` is honoured in both directions: a request with `no-store` bypasses the cache,
+/// one with `no-cache` or `max-age=0` is answered fresh (and the answer stored); a response with
+/// `no-store`, `no-cache` or `private` is not stored, and its `s-maxage` or `max-age` overrides
+/// the default time to live. Responses setting cookies are never stored, and requests with
+/// `Authorization` are only cached if it is one of the `vary` headers. Hits carry an `Age` header.
+///
+/// When the cache is over its size limit, the least recently used responses are evicted.
+pub struct ResponseCache {
+    store: Mutex<Store>,
+    ttl: Duration,
+    max_bytes: usize,
+    vary: Vec<String>,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+    /// Keys by last use, oldest first.
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: usize,
+    /// Bumped by every invalidation, so responses rendered before one are not stored after it.
+    generation: u64,
+}
+
+struct Entry {
+    /// Path and query string, matched by [`ResponseCache::invalidate`].
+    target: String,
+    /// The response exactly as the handler wrote it.
+    response: Vec<u8>,
+    stored: Instant,
+    expires: Instant,
+    last_used: u64,
+}
+
+impl Store {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.last_used);
+            entry.last_used = clock;
+            self.recency.insert(clock, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.bytes -= entry.response.len();
+        }
+    }
+}
+
+impl ResponseCache {
+    /// A cache holding up to `max_bytes` of responses, each for 60 seconds by default.
+    pub fn new(max_bytes: usize) -> Self {
+        Self { store: Mutex::default(), ttl: Duration::from_secs(60), max_bytes, vary: Vec::new() }
+    }
+
+    /// How long responses without `max-age` are served from the cache.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Keep separate responses per value of request header `name`, e.g. `Authorization` for
+    /// per-user responses or `Accept` for negotiated ones.
+    pub fn vary(mut self, name: &str) -> Self {
+        self.vary.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Drop every cached response whose path (with query) starts with `prefix`, e.g. `/api/notes`
+    /// for the list and every note under it. Returns how many were dropped.
+    pub fn invalidate(&self, prefix: &str) -> usize {
+        let mut store = self.store.lock().unwrap();
+        store.generation += 1;
+        let stale: Vec<String> = store.entries.iter().filter(|(_, entry)| entry.target.starts_with(prefix)).map(|(key, _)| key.clone()).collect();
+        for key in &stale {
+            store.remove(key);
+        }
+        stale.len()
+    }
+
+    /// Drop every cached response.
+    pub fn clear(&self) -> usize {
+        self.invalidate("")
+    }
+
+    /// Number of cached responses and their total size in bytes.
+    pub fn usage(&self) -> (usize, usize) {
+        let store = self.store.lock().unwrap();
+        (store.entries.len(), store.bytes)
+    }
+
+    fn key(&self, req: &Request) -> String {
+        let mut key = req.path.clone();
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(req.header(name).unwrap_or(""));
+        }
+        key
+    }
+
+    /// Store `response` unless the cache was invalidated since `generation`.
+    fn insert(&self, key: String, target: &str, response: Vec<u8>, ttl: Duration, generation: u64) {
+        let mut store = self.store.lock().unwrap();
+        if store.generation != generation || response.len() > self.max_bytes {
+            return;
+        }
+        store.remove(&key);
+        while store.bytes + response.len() > self.max_bytes {
+            let Some((_, oldest)) = store.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = store.entries.remove(&oldest) {
+                store.bytes -= entry.response.len();
+            }
+        }
+        let now = Instant::now();
+        store.bytes += response.len();
+        store.entries.insert(key.clone(), Entry { target: target.to_string(), response, stored: now, expires: now + ttl, last_used: 0 });
+        store.touch(&key);
+    }
+}
+
+/// The directives of a `Cache-Control` header, lower-cased, with their values.
+fn directives(value: Option<&str>) -> Vec<(String, Option<String>)> {
+    value
+        .unwrap_or("")
+        .split(',')
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| match d.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim().trim_matches('"').to_string())),
+            None => (d.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+impl Middleware for ResponseCache {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        let request_directives = directives(req.header("cache-control"));
+        let has = |name: &str| request_directives.iter().any(|(n, _)| n == name);
+        let shared_credentials = req.header("authorization").is_some() && !self.vary.iter().any(|v| v == "authorization");
+        if req.method != "GET" || has("no-store") || shared_credentials {
+            return next.run(req, stream);
+        }
+
+        let key = self.key(req);
+        let fresh_only = has("no-cache") || request_directives.iter().any(|(n, v)| n == "max-age" && v.as_deref() == Some("0"));
+        let generation = {
+            let mut store = self.store.lock().unwrap();
+            let hit = match store.entries.get(&key) {
+                Some(entry) if entry.expires > Instant::now() && !fresh_only => Some((entry.response.clone(), entry.stored.elapsed().as_secs())),
+                Some(_) => {
+                    store.remove(&key);
+                    None
+                }
+                None => None,
+            };
+            if let Some((response, age)) = hit {
+                store.touch(&key);
+                drop(store);
+                let mut writer = InjectHeaders::new(stream, &[("Age", &age.to_string())]);
+                writer.write_all(&response)?;
+                return writer.flush();
+            }
+            store.generation
+        };
+
+        let mut tee = Tee::new(stream, self.max_bytes);
+        next.run(req, &mut tee)?;
+        if tee.overflowed {
+            return Ok(());
+        }
+        let Ok(Some((resp, _))) = client::read_response(&mut Cursor::new(&tee.captured), &mut RequestParser::new(), &req.method, u64::MAX) else {
+            return Ok(());
+        };
+        let response_directives = directives(resp.header("cache-control"));
+        let forbidden = response_directives.iter().any(|(n, _)| matches!(n.as_str(), "no-store" | "no-cache" | "private"));
+        if resp.status != 200 || forbidden || resp.header("set-cookie").is_some() {
+            return Ok(());
+        }
+        let max_age = |name: &str| response_directives.iter().find(|(n, _)| n == name).and_then(|(_, v)| v.as_deref()?.parse::<u64>().ok()).map(Duration::from_secs);
+        let ttl = max_age("s-maxage").or_else(|| max_age("max-age")).unwrap_or(self.ttl);
+        if !ttl.is_zero() {
+            self.insert(key, &req.path, tee.captured, ttl, generation);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router, write_response, write_response_with_headers};
+
+    #[test]
+    fn response_cache_serves_varies_and_invalidates() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let renders = Arc::new(AtomicUsize::new(0));
+        let cache = Arc::new(ResponseCache::new(4096).vary("authorization"));
+        let mut router = Router::new();
+        for path in ["/notes", "/notes/:id", "/private"] {
+            let renders = Arc::clone(&renders);
+            router
+                .add_route(Method::Get, path, move |req, stream| {
+                    let n = renders.fetch_add(1, Ordering::SeqCst);
+                    let cache_control = if req.path == "/private" { "private" } else { "max-age=3600" };
+                    write_response_with_headers(stream, 200, "OK", "text/plain", &[("Cache-Control", cache_control)], format!("{} {}", req.path, n).as_bytes())
+                })
+                .wrap(Arc::clone(&cache));
+        }
+        let get = |path: &str, headers: &[(&str, &str)]| {
+            let mut req = Request::new("GET", path);
+            for (name, value) in headers {
+                req.headers.insert(name.to_string(), value.to_string());
+            }
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert!(get("/notes", &[]).ends_with("/notes 0"));
+        let hit = get("/notes", &[]);
+        assert!(hit.ends_with("/notes 0") && hit.contains("\r\nAge: 0\r\n"), "{}", hit);
+        assert!(get("/notes", &[("authorization", "Bearer a")]).ends_with("/notes 1"));
+        assert!(get("/notes", &[("authorization", "Bearer a")]).ends_with("/notes 1"));
+        assert!(get("/notes", &[("cache-control", "no-cache")]).ends_with("/notes 2"));
+        assert!(get("/notes", &[]).ends_with("/notes 2"));
+        assert!(get("/notes?page=2", &[("cache-control", "no-store")]).ends_with("/notes?page=2 3"));
+        assert!(get("/private", &[]).ends_with("/private 4"));
+        assert!(get("/private", &[]).ends_with("/private 5"));
+        assert_eq!(cache.usage().0, 2);
+
+        assert!(get("/notes/1", &[]).ends_with("/notes/1 6"));
+        assert_eq!(cache.invalidate("/notes/"), 1);
+        assert!(get("/notes/1", &[]).ends_with("/notes/1 7"));
+        assert_eq!(cache.invalidate("/notes"), 3);
+        assert!(get("/notes", &[]).ends_with("/notes 8"));
+    }
+
+    #[test]
+    fn response_cache_evicts_least_recently_used() {
+        use std::sync::Arc;
+        let response_size = {
+            let mut out = Vec::new();
+            write_response(&mut out, 200, "OK", "text/plain", b"/a").unwrap();
+            out.len()
+        };
+        let cache = Arc::new(ResponseCache::new(response_size * 2));
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/:page", |req, stream| write_response(stream, 200, "OK", "text/plain", req.path.as_bytes())).wrap(Arc::clone(&cache));
+        let hit = |path: &str| {
+            let mut out = Vec::new();
+            router.handle(Request::new("GET", path), &mut out).unwrap();
+            String::from_utf8(out).unwrap().contains("\r\nAge: ")
+        };
+        assert!(!hit("/a") && !hit("/b"));
+        assert!(hit("/a"));
+        assert!(!hit("/c"));
+        assert_eq!(cache.usage(), (2, response_size * 2));
+        // `/b` was least recently used, so `/c` replaced it.
+        assert!(hit("/a") && hit("/c") && !hit("/b"));
+    }
+
+    #[test]
+    fn response_cache_stores_close_delimited_responses() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let renders = Arc::new(AtomicUsize::new(0));
+        let mut router = Router::new();
+        let counter = Arc::clone(&renders);
+        router
+            .add_route(Method::Get, "/stream", move |_req, stream| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nuntil close")
+            })
+            .wrap(ResponseCache::new(4096));
+        for _ in 0..2 {
+            let mut out = Vec::new();
+            router.handle(Request::new("GET", "/stream"), &mut out).unwrap();
+            assert!(String::from_utf8(out).unwrap().ends_with("\r\n\r\nuntil close"));
+        }
+        assert_eq!(renders.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,428 @@
+//! A small blocking HTTP/1.1 client for calling other services.
+//!
+//! Responses are read with the same [`RequestParser`] and [`Body`] the server uses for requests.
+//! Only plain `http://` URLs are supported.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::body::{Body, BodyTooLarge, Framing};
+use crate::request::RequestParser;
+use crate::router::Method;
+
+/// Default for [`Client::max_response_bytes`].
+pub const MAX_RESPONSE_BYTES: u64 = 16 * 1024 * 1024;
+/// Default for [`Client::max_redirects`].
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+/// Idle keep-alive connections kept per host.
+const MAX_IDLE_PER_HOST: usize = 4;
+
+/// A blocking HTTP client that keeps connections to keep-alive servers open for reuse.
+///
+/// Share one client (e.g. in an `Arc`) rather than creating one per request, so connections are
+/// reused.
+pub struct Client {
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    max_response_bytes: u64,
+    idle: Mutex<HashMap<String, Vec<TcpStream>>>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self { timeout: None, max_redirects: DEFAULT_MAX_REDIRECTS, max_response_bytes: MAX_RESPONSE_BYTES, idle: Mutex::new(HashMap::new()) }
+    }
+
+    /// Limit connecting, and each read and write, to `timeout`. By default there is no limit.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Follow at most `n` redirects per request; zero returns redirects as they are.
+    pub fn max_redirects(mut self, n: usize) -> Self {
+        self.max_redirects = n;
+        self
+    }
+
+    /// Refuse response bodies larger than `limit` bytes.
+    pub fn max_response_bytes(mut self, limit: u64) -> Self {
+        self.max_response_bytes = limit;
+        self
+    }
+
+    pub fn get(&self, url: &str) -> io::Result<ClientResponse> {
+        self.request(Method::Get, url).send()
+    }
+
+    pub fn post(&self, url: &str, content_type: &str, body: impl Into<Vec<u8>>) -> io::Result<ClientResponse> {
+        self.request(Method::Post, url).header("Content-Type", content_type).body(body).send()
+    }
+
+    /// Start building a request; finish it with [`ClientRequest::send`].
+    pub fn request(&self, method: Method, url: &str) -> ClientRequest<'_> {
+        ClientRequest { client: self, method: method.as_str(), url: url.to_string(), headers: Vec::new(), body: Vec::new() }
+    }
+
+    /// Send one request and read its response, on an idle connection if there is one.
+    fn execute(&self, method: &str, url: &Url, headers: &[(String, String)], body: &[u8]) -> io::Result<ClientResponse> {
+        let head = request_head(method, url, headers, body);
+        if let Some(stream) = self.checkout(url) {
+            // The server may have closed an idle connection; if so, retry once on a new one.
+            match self.exchange(stream, method, url, &head, body) {
+                Ok(Some(resp)) => return Ok(resp),
+                Ok(None) => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let stream = self.connect(url)?;
+        self.exchange(stream, method, url, &head, body)?.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before response"))
+    }
+
+    /// Write a request and read the response. Returns `Ok(None)` if the connection closed
+    /// before any of the response arrived.
+    fn exchange(&self, mut stream: TcpStream, method: &str, url: &Url, head: &[u8], body: &[u8]) -> io::Result<Option<ClientResponse>> {
+        stream.write_all(head)?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut parser = RequestParser::new();
+        let Some((resp, delimited_by_close)) = read_response(&mut stream, &mut parser, method, self.max_response_bytes)? else {
+            return Ok(None);
+        };
+
+        // Bytes past the response mean the framing is not what we think; do not reuse.
+        if !delimited_by_close && parser.buffered_body().is_empty() && resp.keep_alive() {
+            self.checkin(url, stream);
+        }
+        Ok(Some(resp))
+    }
+
+    fn connect(&self, url: &Url) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", url.host));
+        for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+            let stream = match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.timeout)?;
+                    stream.set_write_timeout(self.timeout)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn checkout(&self, url: &Url) -> Option<TcpStream> {
+        self.idle.lock().unwrap().get_mut(&url.authority())?.pop()
+    }
+
+    fn checkin(&self, url: &Url, stream: TcpStream) {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.entry(url.authority()).or_default();
+        if conns.len() < MAX_IDLE_PER_HOST {
+            conns.push(stream);
+        }
+    }
+}
+
+/// A request being built by [`Client::request`].
+pub struct ClientRequest<'c> {
+    client: &'c Client,
+    method: &'static str,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl ClientRequest<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Send the request, following redirects up to the client's limit.
+    ///
+    /// `303` responses, and `301`/`302` responses to a `POST`, are followed with a bodiless `GET`;
+    /// other redirects repeat the original method and body. `Authorization` is dropped when a
+    /// redirect leads to another host.
+    pub fn send(self) -> io::Result<ClientResponse> {
+        let ClientRequest { client, mut method, url, mut headers, mut body } = self;
+        let mut url = Url::parse(&url)?;
+        let mut redirects = 0;
+        loop {
+            let resp = client.execute(method, &url, &headers, &body)?;
+            let location = match (resp.status, resp.header("location")) {
+                (301 | 302 | 303 | 307 | 308, Some(location)) if client.max_redirects > 0 => location,
+                _ => return Ok(resp),
+            };
+            if redirects == client.max_redirects {
+                return Err(io::Error::other(format!("more than {} redirects", client.max_redirects)));
+            }
+            redirects += 1;
+
+            let next = url.join(location)?;
+            if next.authority() != url.authority() {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("authorization"));
+            }
+            if resp.status == 303 || (matches!(resp.status, 301 | 302) && method == "POST") {
+                method = "GET";
+                body.clear();
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("content-type"));
+            }
+            url = next;
+        }
+    }
+}
+
+/// A response read by [`Client`].
+#[derive(Clone, Debug)]
+pub struct ClientResponse {
+    pub status: u16,
+    pub reason: String,
+    /// Protocol version from the status line, e.g. "HTTP/1.1".
+    pub version: String,
+    /// Lower-cased header names mapped to their values.
+    pub headers: HashMap<String, String>,
+    /// The body, with any chunked framing removed.
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    /// Look up a header by name, ignoring ASCII case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    /// The body as text, replacing invalid UTF-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Whether the server will keep the connection open after this response.
+    fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").unwrap_or("");
+        let has = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+        if self.version == "HTTP/1.0" { has("keep-alive") } else { !has("close") }
+    }
+}
+
+/// Read the response to a `method` request from `stream`, skipping interim responses. Returns
+/// `Ok(None)` if the stream ends before any of it, and otherwise whether the body was delimited
+/// by the connection closing.
+pub(crate) fn read_response(stream: &mut impl Read, parser: &mut RequestParser, method: &str, max_bytes: u64) -> io::Result<Option<(ClientResponse, bool)>> {
+    let (status, reason, version, headers) = loop {
+        let Some(raw) = parser.read_head(stream)? else {
+            return Ok(None);
+        };
+        let (version, status, reason) = parse_status_line(raw.start_line())?;
+        let headers = raw.to_request(Vec::new()).headers;
+        // Skip interim responses such as `100 Continue`.
+        if (100..200).contains(&status) {
+            parser.consume();
+            continue;
+        }
+        break (status, reason, version, headers);
+    };
+
+    let mut resp = ClientResponse { status, reason, version, headers, body: Vec::new() };
+    let no_body = method == "HEAD" || status == 204 || status == 304;
+    let delimited_by_close = !no_body && resp.header("transfer-encoding").is_none() && resp.header("content-length").is_none();
+    {
+        let mut source = parser.body_reader(stream);
+        if delimited_by_close {
+            (&mut source).take(max_bytes.saturating_add(1)).read_to_end(&mut resp.body)?;
+            if resp.body.len() as u64 > max_bytes {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, BodyTooLarge { limit: max_bytes }));
+            }
+        } else if !no_body {
+            let framing = match resp.header("transfer-encoding") {
+                Some(_) => Framing::Chunked,
+                None => resp.header("content-length").and_then(|len| len.trim().parse().ok()).map(Framing::Length).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length"))?,
+            };
+            resp.body = Body::new(&mut source, framing, max_bytes).read_to_vec()?;
+        }
+    }
+    parser.consume();
+    Ok(Some((resp, delimited_by_close)))
+}
+
+pub(crate) fn parse_status_line(line: &str) -> io::Result<(String, u16, String)> {
+    let bad = || io::Error::new(io::ErrorKind::InvalidData, format!("bad status line: {:?}", line));
+    let (version, rest) = line.split_once(' ').ok_or_else(bad)?;
+    let (code, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+    if !version.starts_with("HTTP/") || code.len() != 3 {
+        return Err(bad());
+    }
+    Ok((version.to_string(), code.parse().map_err(|_| bad())?, reason.to_string()))
+}
+
+fn request_head(method: &str, url: &Url, headers: &[(String, String)], body: &[u8]) -> Vec<u8> {
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, url.path, url.host_header());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() || matches!(method, "POST" | "PUT" | "PATCH") {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+/// The parts of an `http://` URL needed to send a request.
+#[derive(Clone, Debug, PartialEq)]
+struct Url {
+    host: String,
+    port: u16,
+    /// Path and query, starting with `/`.
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> io::Result<Url> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", msg, url));
+        let scheme_end = url.find("://").ok_or_else(|| invalid("not an absolute URL"))?;
+        if !url[..scheme_end].eq_ignore_ascii_case("http") {
+            return Err(invalid("only http URLs are supported"));
+        }
+        let rest = &url[scheme_end + 3..];
+        let rest = rest.split('#').next().unwrap_or("");
+        let split = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(split);
+        let path = if path.starts_with('?') { format!("/{}", path) } else if path.is_empty() { "/".to_string() } else { path.to_string() };
+
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        let (host, port) = match authority.rfind(':') {
+            Some(colon) if !authority[colon..].contains(']') => (&authority[..colon], authority[colon + 1..].parse().map_err(|_| invalid("bad port"))?),
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        Ok(Url { host: host.to_string(), port, path })
+    }
+
+    /// Resolve a `Location` header against this URL.
+    fn join(&self, location: &str) -> io::Result<Url> {
+        if location.contains("://") {
+            Url::parse(location)
+        } else if let Some(rest) = location.strip_prefix("//") {
+            Url::parse(&format!("http://{}", rest))
+        } else if location.starts_with('/') {
+            Ok(Url { path: location.to_string(), ..self.clone() })
+        } else {
+            let base = self.path.split('?').next().unwrap_or("/");
+            let dir = &base[..base.rfind('/').map_or(0, |i| i + 1)];
+            Ok(Url { path: format!("{}{}", dir, location), ..self.clone() })
+        }
+    }
+
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == 80 { host } else { format!("{}:{}", host, self.port) }
+    }
+
+    fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Router, Server, server, write_response};
+
+    /// A keep-alive server answering from `respond(path)` until each connection closes. Returns
+    /// its address and a count of accepted connections.
+    fn keep_alive_server(respond: fn(&str) -> String) -> (std::net::SocketAddr, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::io::Write;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&accepted);
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut parser = RequestParser::new();
+                    while let Ok(Some(req)) = parser.read_request(&mut stream) {
+                        if stream.write_all(respond(&req.path).as_bytes()).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (addr, accepted)
+    }
+
+    #[test]
+    fn client_reads_server_responses() {
+        let mut router = Router::new();
+        router.add_route(Method::Post, "/echo", |req, stream| write_response(stream, 201, "Created", "text/plain", &req.body));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(router);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                server::serve_tcp(stream, &server).unwrap();
+            }
+        });
+
+        let client = Client::new().timeout(std::time::Duration::from_secs(5));
+        let resp = client.post(&format!("http://{}/echo", addr), "text/plain", "ping").unwrap();
+        assert_eq!((resp.status, resp.reason.as_str()), (201, "Created"));
+        assert_eq!(resp.header("Content-Type"), Some("text/plain"));
+        assert_eq!(resp.text(), "ping");
+
+        let resp = client.request(Method::Get, &format!("http://{}/missing", addr)).header("X-Request-Id", "c-1").send().unwrap();
+        assert_eq!(resp.status, 404);
+        assert_eq!(resp.header("x-request-id"), Some("c-1"));
+    }
+
+    #[test]
+    fn client_follows_redirects_and_reuses_connections() {
+        use std::sync::atomic::Ordering;
+        let (addr, accepted) = keep_alive_server(|path| match path {
+            "/old" => "HTTP/1.1 302 Found\r\nLocation: /chunked\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "/chunked" => "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n".to_string(),
+            _ => "HTTP/1.1 301 Moved Permanently\r\nLocation: loop\r\nContent-Length: 0\r\n\r\n".to_string(),
+        });
+        let client = Client::new().max_redirects(3);
+
+        let resp = client.get(&format!("http://{}/old", addr)).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.text(), "hello world");
+        let resp = client.get(&format!("http://{}/chunked", addr)).unwrap();
+        assert_eq!(resp.text(), "hello world");
+
+        let err = client.get(&format!("http://{}/loop", addr)).unwrap_err();
+        assert!(err.to_string().contains("more than 3 redirects"), "{}", err);
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        assert_eq!(client.get("https://example.com/").unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
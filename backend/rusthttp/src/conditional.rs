@@ -0,0 +1,243 @@
+//! Conditional requests (RFC 9110 section 13): entity tags, `Last-Modified` and the `If-*`
+//! request headers.
+//!
+//! [`ConditionalGet`] handles `GET` and `HEAD` for any route by looking at the response the
+//! handler wrote. Unsafe methods have to be checked before the handler changes anything, so
+//! handlers call [`evaluate`] with the resource's current validators instead:
+//!
+//! ```rust,no_run
+//! use rusthttp::{HttpError, Method, Router, conditional::{self, Precondition}};
+//!
+//! let mut router = Router::new();
+//! router.add_route(Method::Delete, "/notes/:id", |req, stream| {
+//!     let current = "\"v7\""; // e.g. derived from the note's version
+//!     if conditional::evaluate(req, Some(current), None) == Precondition::Failed {
+//!         return req.send_error(stream, HttpError::new(412, "precondition failed"));
+//!     }
+//!     // ... delete it
+//!     rusthttp::write_response(stream, 204, "No Content", "text/plain", b"")
+//! });
+//! ```
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::date::parse_http_date;
+use crate::error::HttpError;
+use crate::middleware::{Middleware, Next};
+use crate::request::Request;
+
+/// Outcome of checking a request's preconditions against the current resource.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// Handle the request normally.
+    Proceed,
+    /// Answer `304 Not Modified` (only for `GET` and `HEAD`).
+    NotModified,
+    /// Answer `412 Precondition Failed`.
+    Failed,
+}
+
+/// Check `If-Match`, `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since` against the
+/// current `etag` (quoted, optionally `W/`-prefixed) and `last_modified` time of the resource,
+/// in the order RFC 9110 section 13.2.2 gives. Pass `None` for both if the resource does not
+/// exist.
+pub fn evaluate(req: &Request, etag: Option<&str>, last_modified: Option<SystemTime>) -> Precondition {
+    let safe = matches!(req.method.as_str(), "GET" | "HEAD");
+    let exists = etag.is_some() || last_modified.is_some();
+    let since = |name: &str| Some((req.header(name).and_then(parse_http_date)?, whole_seconds(last_modified?)));
+
+    if let Some(value) = req.header("if-match") {
+        if !matches_any(value, etag, exists, strong_eq) {
+            return Precondition::Failed;
+        }
+    } else if let Some((date, modified)) = since("if-unmodified-since")
+        && modified > date
+    {
+        return Precondition::Failed;
+    }
+
+    if let Some(value) = req.header("if-none-match") {
+        if matches_any(value, etag, exists, weak_eq) {
+            return if safe { Precondition::NotModified } else { Precondition::Failed };
+        }
+    } else if safe
+        && let Some((date, modified)) = since("if-modified-since")
+        && modified <= date
+    {
+        return Precondition::NotModified;
+    }
+    Precondition::Proceed
+}
+
+/// A strong entity tag for `body`: a quoted 64-bit FNV-1a hash, stable across runs and builds.
+pub fn etag_for(body: &[u8]) -> String {
+    let hash = body.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3));
+    format!("\"{:016x}\"", hash)
+}
+
+/// HTTP dates have one-second resolution, so compare modification times at that resolution too.
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    UNIX_EPOCH + std::time::Duration::from_secs(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+}
+
+/// Whether an `If-Match`/`If-None-Match` value matches the current tag.
+fn matches_any(value: &str, etag: Option<&str>, exists: bool, eq: fn(&str, &str) -> bool) -> bool {
+    if value.trim() == "*" {
+        return exists;
+    }
+    etag.is_some_and(|etag| entity_tags(value).any(|tag| eq(tag, etag)))
+}
+
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && a == b
+}
+
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+/// The entity tags in a comma-separated list, stopping at the first malformed one. Tags may
+/// contain commas, so the list is scanned rather than split.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        let start = rest;
+        let quoted = rest.strip_prefix("W/").unwrap_or(rest).strip_prefix('"')?;
+        let close = quoted.find('"')?;
+        let len = start.len() - quoted.len() + close + 1;
+        rest = &start[len..];
+        Some(&start[..len])
+    })
+}
+
+/// Middleware answering conditional `GET` and `HEAD` requests for the routes it wraps.
+///
+/// Successful responses get an `ETag` (a hash of the body) unless the handler set one, and a
+/// request whose `If-None-Match` or `If-Modified-Since` still matches is answered with
+/// `304 Not Modified` and no body. A `Last-Modified` header from the handler is honored too.
+/// The response is buffered to do this, so wrap routes with small bodies rather than
+/// streaming ones. Other methods pass straight through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConditionalGet;
+
+impl ConditionalGet {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Middleware for ConditionalGet {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        if !matches!(req.method.as_str(), "GET" | "HEAD") {
+            return next.run(req, stream);
+        }
+        let mut response = Vec::new();
+        next.run(req, &mut response)?;
+
+        let Some(end) = response.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return stream.write_all(&response);
+        };
+        let (Ok(head), body) = (std::str::from_utf8(&response[..end]), &response[end + 4..]) else {
+            return stream.write_all(&response);
+        };
+        if head.get(9..12) != Some("200") {
+            return stream.write_all(&response);
+        }
+        let mut lines = head.split("\r\n");
+        lines.next();
+        let headers: Vec<(&str, &str)> = lines.filter_map(|line| line.split_once(':')).map(|(name, value)| (name, value.trim())).collect();
+        let header = |name: &str| headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| *value);
+
+        // A HEAD response has no body to hash.
+        let computed = (header("etag").is_none() && req.method == "GET").then(|| etag_for(body));
+        let etag = header("etag").or(computed.as_deref());
+        let last_modified = header("last-modified").and_then(parse_http_date);
+
+        let (mut out, send_body) = match evaluate(req, etag, last_modified) {
+            Precondition::Proceed => (format!("{}\r\n", head), true),
+            Precondition::NotModified => {
+                let mut out = "HTTP/1.1 304 Not Modified\r\n".to_string();
+                for (name, value) in &headers {
+                    if !["content-type", "content-length", "transfer-encoding"].iter().any(|h| name.eq_ignore_ascii_case(h)) {
+                        out.push_str(&format!("{}: {}\r\n", name, value));
+                    }
+                }
+                (out, false)
+            }
+            Precondition::Failed => return req.send_error(stream, HttpError::new(412, "precondition failed")),
+        };
+        if let Some(etag) = &computed {
+            out.push_str(&format!("ETag: {}\r\n", etag));
+        }
+        out.push_str("\r\n");
+        stream.write_all(out.as_bytes())?;
+        if send_body { stream.write_all(body) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router, write_response, write_response_with_headers};
+
+    #[test]
+    fn preconditions_follow_rfc_order() {
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_millis(971_186_136_500);
+        let check = |method: &str, headers: &[(&str, &str)], etag: Option<&str>| {
+            let mut req = Request::new(method, "/notes/1");
+            for (name, value) in headers {
+                req.headers.insert(name.to_string(), value.to_string());
+            }
+            evaluate(&req, etag, Some(modified))
+        };
+        let tag = Some("\"v2\"");
+        assert_eq!(check("GET", &[("if-none-match", "\"v1\", W/\"v2\"")], tag), Precondition::NotModified);
+        assert_eq!(check("GET", &[("if-none-match", "\"a,b\", \"v1\"")], tag), Precondition::Proceed);
+        assert_eq!(check("GET", &[("if-modified-since", "Tue, 10 Oct 2000 13:55:36 GMT")], tag), Precondition::NotModified);
+        assert_eq!(check("GET", &[("if-modified-since", "Tue, 10 Oct 2000 13:55:35 GMT")], tag), Precondition::Proceed);
+        // If-None-Match takes precedence over If-Modified-Since.
+        assert_eq!(check("GET", &[("if-none-match", "\"v1\""), ("if-modified-since", "Tue, 10 Oct 2000 13:55:36 GMT")], tag), Precondition::Proceed);
+        assert_eq!(check("PATCH", &[("if-match", "\"v2\"")], tag), Precondition::Proceed);
+        assert_eq!(check("PATCH", &[("if-match", "\"v1\"")], tag), Precondition::Failed);
+        assert_eq!(check("PATCH", &[("if-match", "W/\"v2\"")], tag), Precondition::Failed);
+        assert_eq!(check("PATCH", &[("if-match", "*")], None), Precondition::Proceed);
+        assert_eq!(check("DELETE", &[("if-none-match", "*")], tag), Precondition::Failed);
+        assert_eq!(check("DELETE", &[("if-unmodified-since", "Tue, 10 Oct 2000 13:55:35 GMT")], tag), Precondition::Failed);
+        assert_eq!(check("DELETE", &[("if-unmodified-since", "not a date")], tag), Precondition::Proceed);
+    }
+
+    #[test]
+    fn conditional_get_answers_not_modified() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/notes", |_req, stream| write_response(stream, 200, "OK", "application/json", b"[1,2,3]")).wrap(ConditionalGet::new());
+        router
+            .add_route(Method::Get, "/dated", |_req, stream| write_response_with_headers(stream, 200, "OK", "text/plain", &[("Last-Modified", "Tue, 10 Oct 2000 13:55:36 GMT")], b"old"))
+            .wrap(ConditionalGet::new());
+
+        let get = |path: &str, header: Option<(&str, &str)>| {
+            let mut req = Request::new("GET", path);
+            if let Some((name, value)) = header {
+                req.headers.insert(name.to_string(), value.to_string());
+            }
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let etag = etag_for(b"[1,2,3]");
+        let first = get("/notes", None);
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(first.contains(&format!("ETag: {}\r\n\r\n[1,2,3]", etag)), "{}", first);
+
+        let cached = get("/notes", Some(("if-none-match", &etag)));
+        assert!(cached.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", cached);
+        assert!(cached.ends_with(&format!("ETag: {}\r\n\r\n", etag)));
+        assert!(!cached.contains("Content-Length"));
+
+        assert!(get("/notes", Some(("if-none-match", "\"stale\""))).starts_with("HTTP/1.1 200 OK"));
+        assert!(get("/notes", Some(("if-match", "\"stale\""))).starts_with("HTTP/1.1 412 Precondition Failed"));
+        assert!(get("/dated", Some(("if-modified-since", "Tue, 10 Oct 2000 13:55:36 GMT"))).starts_with("HTTP/1.1 304 "));
+        assert!(get("/dated", Some(("if-modified-since", "Mon, 09 Oct 2000 13:55:36 GMT"))).ends_with("\r\n\r\nold"));
+    }
+}
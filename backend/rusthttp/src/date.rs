@@ -0,0 +1,115 @@
+//! Timestamp formatting for logs and headers, without a date library.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// UTC calendar fields of a timestamp.
+struct Civil {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    millis: u32,
+}
+
+impl Civil {
+    fn from(time: SystemTime) -> Civil {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs() as i64;
+        let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+        let (year, month, day) = civil_from_days(days);
+        Civil { year, month, day, hour: (rem / 3600) as u32, minute: (rem % 3600 / 60) as u32, second: (rem % 60) as u32, millis: since.subsec_millis() }
+    }
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date.
+///
+/// Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) date to days since 1970-01-01; the inverse of [`civil_from_days`].
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Common Log Format timestamp, e.g. `10/Oct/2000:13:55:36 +0000`.
+pub fn format_clf(time: SystemTime) -> String {
+    let c = Civil::from(time);
+    format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000", c.day, MONTHS[c.month as usize - 1], c.year, c.hour, c.minute, c.second)
+}
+
+/// RFC 3339 UTC timestamp with milliseconds, e.g. `2000-10-10T13:55:36.000Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let c = Civil::from(time);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", c.year, c.month, c.day, c.hour, c.minute, c.second, c.millis)
+}
+
+/// HTTP-date (RFC 9110 IMF-fixdate), e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    let c = Civil::from(time);
+    let weekday = days_from_civil(c.year, c.month, c.day).rem_euclid(7) as usize;
+    format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT", WEEKDAYS[weekday], c.day, MONTHS[c.month as usize - 1], c.year, c.hour, c.minute, c.second)
+}
+
+/// Parse an IMF-fixdate HTTP-date. The obsolete RFC 850 and asctime forms are not accepted, so
+/// conditional headers using them are ignored, as RFC 9110 allows for invalid dates.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let (weekday, rest) = value.trim().split_once(", ")?;
+    let fields: Vec<&str> = rest.split(' ').collect();
+    let [day, month, year, time, "GMT"] = fields[..] else {
+        return None;
+    };
+    let day: u32 = day.parse().ok().filter(|d| (1..=31).contains(d) && day.len() == 2)?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let year: i64 = year.parse().ok().filter(|_| year.len() == 4)?;
+    let mut hms = time.split(':').map(|part| part.parse::<u64>().ok().filter(|_| part.len() == 2));
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    if hms.next().is_some() || hour > 23 || minute > 59 || second > 60 || year < 1970 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) || WEEKDAYS[days.rem_euclid(7) as usize] != weekday {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400 + hour * 3600 + minute * 60 + second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clf_dates() {
+        let t = std::time::UNIX_EPOCH + std::time::Duration::from_secs(971_186_136);
+        assert_eq!(format_clf(t), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(format_rfc3339(t), "2000-10-10T13:55:36.000Z");
+        let leap = std::time::UNIX_EPOCH + std::time::Duration::from_secs(951_782_400);
+        assert_eq!(format_rfc3339(leap), "2000-02-29T00:00:00.000Z");
+        assert_eq!(format_http_date(t), "Tue, 10 Oct 2000 13:55:36 GMT");
+        assert_eq!(parse_http_date("Tue, 10 Oct 2000 13:55:36 GMT"), Some(t));
+        assert_eq!(parse_http_date(&format_http_date(leap)), Some(leap));
+        assert_eq!(parse_http_date("Wed, 10 Oct 2000 13:55:36 GMT"), None);
+        assert_eq!(parse_http_date("Tuesday, 10-Oct-00 13:55:36 GMT"), None);
+    }
+}
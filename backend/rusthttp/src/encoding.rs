@@ -0,0 +1,195 @@
+//! Decoding of compressed request bodies, enabled with the `compression` cargo feature.
+
+use std::io::{self, BufRead, Read};
+
+use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+
+use crate::body::{Body, Framing, is_too_large};
+use crate::error::HttpError;
+use crate::request::Request;
+
+/// The content codings request bodies can be decoded from, as sent in `Accept-Encoding`.
+pub(crate) const SUPPORTED: &str = "gzip, deflate";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Coding {
+    Gzip,
+    /// The zlib format, as `deflate` is defined for HTTP.
+    Deflate,
+}
+
+/// The codings of `req`'s body in the order they were applied, or a `415 Unsupported Media Type`
+/// error listing the supported ones.
+pub(crate) fn request_codings(req: &Request) -> Result<Vec<Coding>, HttpError> {
+    let mut codings = Vec::new();
+    for coding in req.header("content-encoding").unwrap_or("").split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        match coding.to_ascii_lowercase().as_str() {
+            "identity" => {}
+            "gzip" | "x-gzip" => codings.push(Coding::Gzip),
+            "deflate" => codings.push(Coding::Deflate),
+            _ => return Err(HttpError::new(415, "unsupported content encoding").header("Accept-Encoding", SUPPORTED)),
+        }
+    }
+    Ok(codings)
+}
+
+/// Once a body is decoded, its encoding and length no longer describe it.
+pub(crate) fn strip_headers(req: &mut Request) {
+    req.headers.retain(|name, _| !name.eq_ignore_ascii_case("content-encoding") && !name.eq_ignore_ascii_case("content-length"));
+}
+
+/// Decode a body already read into `req.body`, allowing at most `limit` decoded bytes.
+pub(crate) fn decode_buffered(req: &mut Request, limit: u64) -> Result<(), HttpError> {
+    if req.body.is_empty() {
+        return Ok(());
+    }
+    let codings = request_codings(req)?;
+    if codings.is_empty() {
+        return Ok(());
+    }
+    let encoded = std::mem::take(&mut req.body);
+    let mut source = io::Cursor::new(&encoded[..]);
+    let mut body = Body::new(&mut source, Framing::Length(encoded.len() as u64), limit);
+    for &coding in codings.iter().rev() {
+        body = body.decoded(coding);
+    }
+    req.body = match body.read_to_vec() {
+        Ok(bytes) => bytes,
+        Err(e) if is_too_large(&e) => return Err(HttpError::new(413, "payload too large")),
+        Err(_) => return Err(HttpError::new(400, "bad request")),
+    };
+    strip_headers(req);
+    Ok(())
+}
+
+enum Inner<R> {
+    Gzip(MultiGzDecoder<R>),
+    Deflate(ZlibDecoder<R>),
+}
+
+/// Decompresses `R`, then reads it to its end so nothing of the body is left on the connection,
+/// failing if anything follows the compressed data.
+pub(crate) struct Decoder<R> {
+    inner: Inner<R>,
+    finished: bool,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub(crate) fn new(reader: R, coding: Coding) -> Self {
+        let inner = match coding {
+            Coding::Gzip => Inner::Gzip(MultiGzDecoder::new(reader)),
+            Coding::Deflate => Inner::Deflate(ZlibDecoder::new(reader)),
+        };
+        Self { inner, finished: false }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        let (n, rest) = match &mut self.inner {
+            Inner::Gzip(decoder) => (decoder.read(buf)?, decoder.get_mut()),
+            Inner::Deflate(decoder) => (decoder.read(buf)?, decoder.get_mut()),
+        };
+        if n == 0 {
+            self.finished = true;
+            if !rest.fill_buf()?.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "data after compressed body"));
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{connect, echo_router};
+    use crate::{Method, write_response};
+
+    fn compress(coding: &str, data: &[u8]) -> Vec<u8> {
+        use flate2::Compression;
+        use std::io::Write;
+        match coding {
+            "gzip" => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn server_decodes_compressed_request_bodies() {
+        use std::io::{Read, Write};
+        let send = |head: &str, body: &[u8]| {
+            let mut router = echo_router(1024);
+            router.add_streaming_route(Method::Post, "/stream", |req, body, stream| {
+                let received = body.read_to_vec()?;
+                let headers = format!("{:?} {:?} ", req.header("content-encoding"), req.header("content-length"));
+                write_response(stream, 200, "OK", "text/plain", &[headers.as_bytes(), &received].concat())
+            });
+            let mut client = connect(router);
+            client.write_all(head.as_bytes()).unwrap();
+            client.write_all(body).unwrap();
+            let mut resp = String::new();
+            client.read_to_string(&mut resp).unwrap();
+            resp
+        };
+
+        let gzipped = compress("gzip", b"hello gzip");
+        let mut chunked = format!("{:x}\r\n", gzipped.len()).into_bytes();
+        chunked.extend_from_slice(&gzipped);
+        chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+        let resp = send("POST /stream HTTP/1.1\r\nContent-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n\r\n", &chunked);
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n") && resp.ends_with("\r\n\r\nNone None hello gzip"), "{}", resp);
+
+        let deflated = compress("deflate", b"hello deflate");
+        let resp = send(&format!("POST /echo HTTP/1.1\r\nContent-Encoding: deflate\r\nContent-Length: {}\r\n\r\n", deflated.len()), &deflated);
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n") && resp.ends_with("\r\n\r\nhello deflate"), "{}", resp);
+
+        let resp = send("POST /echo HTTP/1.1\r\nContent-Encoding: br\r\nContent-Length: 2\r\n\r\n", b"hi");
+        assert!(resp.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"), "{}", resp);
+        assert!(resp.contains("Accept-Encoding: gzip, deflate\r\n"), "{}", resp);
+
+        let mut trailing = deflated.clone();
+        trailing.extend_from_slice(b"junk");
+        let resp = send(&format!("POST /echo HTTP/1.1\r\nContent-Encoding: deflate\r\nContent-Length: {}\r\n\r\n", trailing.len()), &trailing);
+        assert!(resp.starts_with("HTTP/1.1 400 "), "{}", resp);
+    }
+
+    #[test]
+    fn compressed_body_limit_applies_to_decoded_size() {
+        use std::io::{Read, Write};
+        let bomb = compress("gzip", &vec![0; 1 << 20]);
+        assert!(bomb.len() < 4096);
+
+        let mut client = connect(echo_router(4096));
+        client.write_all(format!("POST /echo HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", bomb.len()).as_bytes()).unwrap();
+        client.write_all(&bomb).unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large"), "{}", resp);
+
+        let mut req = Request::new("POST", "/echo");
+        req.headers.insert("content-encoding".to_string(), "gzip".to_string());
+        req.body = bomb;
+        let mut out = Vec::new();
+        echo_router(4096).handle(req, &mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).starts_with("HTTP/1.1 413 "));
+
+        let mut req = Request::new("POST", "/echo");
+        req.headers.insert("content-encoding".to_string(), "gzip".to_string());
+        req.body = b"not gzip".to_vec();
+        let mut out = Vec::new();
+        echo_router(4096).handle(req, &mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).starts_with("HTTP/1.1 400 "));
+    }
+}
@@ -0,0 +1,175 @@
+//! Error responses, rendered in one place.
+//!
+//! Every error response the crate produces (`400`, `404`, `405`, `413`, `429`, `502`, ...) is an
+//! [`HttpError`] rendered by the router's [`ErrorHandler`]. Handlers can send their own errors
+//! the same way with [`Request::send_error`].
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::json;
+use crate::request::Request;
+use crate::response::write_response_with_headers;
+
+/// An error response before it is rendered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpError {
+    pub status: u16,
+    /// Short lower-case description, e.g. "not found".
+    pub message: String,
+    /// Extra response headers, e.g. `Retry-After`.
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into(), headers: Vec::new() }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The standard reason phrase for the status, e.g. "Not Found".
+    pub fn reason(&self) -> &'static str {
+        reason_phrase(self.status)
+    }
+}
+
+/// Standard reason phrase for `status`, or "Error" for codes without one.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        417 => "Expectation Failed",
+        422 => "Unprocessable Content",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Error",
+    }
+}
+
+/// Turns an [`HttpError`] into a response body. Implemented for closures returning
+/// `(content_type, body)`.
+///
+/// Status line, `error.headers` and the standard headers are written around the result.
+pub trait ErrorHandler: Send + Sync {
+    /// `req` is `None` when the request head could not be parsed.
+    fn render(&self, req: Option<&Request>, error: &HttpError) -> (String, Vec<u8>);
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Fn(Option<&Request>, &HttpError) -> (String, Vec<u8>) + Send + Sync,
+{
+    fn render(&self, req: Option<&Request>, error: &HttpError) -> (String, Vec<u8>) {
+        self(req, error)
+    }
+}
+
+/// The default: `{"error":"<message>"}` as `application/json`.
+pub struct JsonErrors;
+
+impl ErrorHandler for JsonErrors {
+    fn render(&self, _req: Option<&Request>, error: &HttpError) -> (String, Vec<u8>) {
+        ("application/json".to_string(), format!("{{\"error\":{}}}", json::string(&error.message)).into_bytes())
+    }
+}
+
+/// RFC 9457 (formerly 7807) problem details as `application/problem+json`, with the request
+/// path as `instance` and the request id as a `request_id` extension member.
+pub struct ProblemJson;
+
+impl ErrorHandler for ProblemJson {
+    fn render(&self, req: Option<&Request>, error: &HttpError) -> (String, Vec<u8>) {
+        let mut body = format!("{{\"type\":\"about:blank\",\"title\":{},\"status\":{},\"detail\":{}", json::string(error.reason()), error.status, json::string(&error.message));
+        if let Some(req) = req {
+            body.push_str(&format!(",\"instance\":{}", json::string(req.path.split('?').next().unwrap_or(""))));
+            if !req.request_id.is_empty() {
+                body.push_str(&format!(",\"request_id\":{}", json::string(&req.request_id)));
+            }
+        }
+        body.push('}');
+        ("application/problem+json".to_string(), body.into_bytes())
+    }
+}
+
+/// The error handler a request was routed with.
+#[derive(Clone)]
+pub(crate) struct SharedErrorHandler(pub(crate) Arc<dyn ErrorHandler>);
+
+impl Default for SharedErrorHandler {
+    fn default() -> Self {
+        Self(Arc::new(JsonErrors))
+    }
+}
+
+impl fmt::Debug for SharedErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+/// Render `error` with `handler` and write the response.
+pub(crate) fn write_error(stream: &mut dyn Write, handler: &dyn ErrorHandler, req: Option<&Request>, error: &HttpError) -> io::Result<()> {
+    let (content_type, body) = handler.render(req, error);
+    let headers: Vec<(&str, &str)> = error.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+    write_response_with_headers(stream, error.status, error.reason(), &content_type, &headers, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use crate::{Method, Router};
+
+    #[test]
+    fn router_renders_errors_through_its_handler() {
+        use std::io::{Read, Write};
+        let mut router = Router::new();
+        router.error_handler(ProblemJson);
+        router.add_route(Method::Post, "/notes", |req, stream| req.send_error(stream, HttpError::new(422, "title is required").header("X-Field", "title")));
+
+        let mut req = Request::new("GET", "/missing?page=2");
+        req.request_id = "abc".to_string();
+        let mut out = Vec::new();
+        router.handle(req, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", out);
+        assert!(out.contains("Content-Type: application/problem+json\r\n"));
+        assert!(out.ends_with(r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"not found","instance":"/missing","request_id":"abc"}"#), "{}", out);
+
+        let mut out = Vec::new();
+        router.handle(Request::new("POST", "/notes"), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 422 Unprocessable Content\r\n"), "{}", out);
+        assert!(out.contains("X-Field: title\r\n"));
+        assert!(out.contains(r#""detail":"title is required""#));
+
+        // Errors from the server itself, before any route runs, use the same handler.
+        let mut stream = connect(router);
+        stream.write_all(b"POST /notes HTTP/1.1\r\nHost: x\r\nContent-Length: nope\r\n\r\n").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", out);
+        assert!(out.contains(r#""detail":"bad request","instance":"/notes""#), "{}", out);
+    }
+}
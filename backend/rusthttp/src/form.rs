@@ -0,0 +1,551 @@
+//! HTML form bodies: `application/x-www-form-urlencoded` and `multipart/form-data`.
+//!
+//! ```rust,no_run
+//! use rusthttp::{Method, Router, form::{Form, Multipart}};
+//!
+//! let mut router = Router::new();
+//! router.add_route(Method::Post, "/login", |req, stream| {
+//!     let form = Form::parse(&req.body);
+//!     let user = form.get("user").unwrap_or("");
+//!     rusthttp::write_response(stream, 200, "OK", "text/plain", user.as_bytes())
+//! });
+//! router.add_streaming_route(Method::Post, "/upload", |req, body, stream| {
+//!     let mut parts = Multipart::from_request(req, body)?;
+//!     while let Some(part) = parts.next_part()? {
+//!         if let Some(filename) = part.filename.clone() {
+//!             part.persist(format!("uploads/{}", filename.replace(['/', '\\'], "_")))?;
+//!         }
+//!     }
+//!     rusthttp::write_response(stream, 204, "No Content", "text/plain", b"")
+//! });
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::body::BodyTooLarge;
+use crate::request::{Request, generate_request_id};
+
+/// Decoded `name=value` pairs of a urlencoded form or query string, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Form {
+    pairs: Vec<(String, String)>,
+}
+
+impl Form {
+    /// Parse `a=1&b=two+words&c=%2F`. Invalid percent escapes are kept literally and invalid
+    /// UTF-8 is replaced, so this never fails.
+    pub fn parse(input: &[u8]) -> Self {
+        let pairs = input
+            .split(|b| *b == b'&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.iter().position(|b| *b == b'=') {
+                Some(eq) => (decode(&pair[..eq]), decode(&pair[eq + 1..])),
+                None => (decode(pair), String::new()),
+            })
+            .collect();
+        Self { pairs }
+    }
+
+    /// The first value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value for `name`, e.g. from repeated checkboxes.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter().filter(move |(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.pairs
+    }
+}
+
+/// Decode `+` and `%XX` escapes.
+fn decode(input: &[u8]) -> String {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        match input[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < input.len() => match (hex(input[i + 1]), hex(input[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push((hi * 16 + lo) as u8);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Limits for [`Multipart`]. Exceeding a size limit fails with [`BodyTooLarge`], which
+/// handlers can answer with `413`.
+#[derive(Clone, Copy, Debug)]
+pub struct MultipartLimits {
+    /// Most parts accepted; default 100.
+    pub max_parts: usize,
+    /// Largest part without a filename (a plain field); default 1 MiB.
+    pub max_field_bytes: u64,
+    /// Largest file part; default 100 MiB.
+    pub max_file_bytes: u64,
+    /// File parts larger than this are written to a temporary file instead of memory; default 64 KiB.
+    pub memory_threshold: usize,
+    /// Largest block of headers for one part; default 16 KiB.
+    pub max_header_bytes: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self { max_parts: 100, max_field_bytes: 1024 * 1024, max_file_bytes: 100 * 1024 * 1024, memory_threshold: 64 * 1024, max_header_bytes: 16 * 1024 }
+    }
+}
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Part {
+    /// The field name from `Content-Disposition`.
+    pub name: String,
+    /// The client's file name, for file inputs. Never use it as a path as is.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    /// All part headers, names lowercased.
+    pub headers: Vec<(String, String)>,
+    data: PartData,
+}
+
+#[derive(Debug)]
+enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+impl Part {
+    /// Size of the content in bytes.
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            PartData::Memory(data) => data.len() as u64,
+            PartData::File(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The content, read back from the temporary file if it was spilled to disk.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(data) => Ok(data.clone()),
+            PartData::File(file) => fs::read(&file.path),
+        }
+    }
+
+    /// The content as text, with invalid UTF-8 replaced.
+    pub fn text(&self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes()?).into_owned())
+    }
+
+    /// The temporary file holding the content, if it was too large to keep in memory. It is
+    /// deleted when the part is dropped.
+    pub fn temp_path(&self) -> Option<&Path> {
+        match &self.data {
+            PartData::Memory(_) => None,
+            PartData::File(file) => Some(&file.path),
+        }
+    }
+
+    /// Read the content as a stream.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            PartData::Memory(data) => Ok(Box::new(&data[..])),
+            PartData::File(file) => Ok(Box::new(File::open(&file.path)?)),
+        }
+    }
+
+    /// Move the content to `path`, renaming the temporary file when possible.
+    pub fn persist(self, path: impl AsRef<Path>) -> io::Result<()> {
+        match self.data {
+            PartData::Memory(data) => fs::write(path, data),
+            PartData::File(file) => {
+                if fs::rename(&file.path, &path).is_err() {
+                    // Across file systems.
+                    fs::copy(&file.path, &path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A temporary file removed on drop.
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Streaming `multipart/form-data` parser: parts are read one at a time with
+/// [`next_part`](Self::next_part), so a large upload never has to fit in memory.
+pub struct Multipart<R> {
+    reader: R,
+    /// `\r\n--boundary`.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    eof: bool,
+    done: bool,
+    parts: usize,
+    limits: MultipartLimits,
+    temp_dir: PathBuf,
+}
+
+const READ_SIZE: usize = 8192;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+impl<R: Read> Multipart<R> {
+    /// Parse `body` using the boundary from `req`'s `Content-Type`, failing with `InvalidInput`
+    /// if it is not `multipart/form-data`.
+    pub fn from_request(req: &Request, body: R) -> io::Result<Self> {
+        let content_type = req.header("content-type").unwrap_or("");
+        let mut params = content_type.split(';');
+        if !params.next().unwrap_or("").trim().eq_ignore_ascii_case("multipart/form-data") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "expected multipart/form-data"));
+        }
+        let boundary = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| value.trim().trim_matches('"'))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "multipart content type without a boundary"))?;
+        Self::new(body, boundary)
+    }
+
+    /// Parse `body` with an explicit `boundary`.
+    pub fn new(body: R, boundary: &str) -> io::Result<Self> {
+        if boundary.is_empty() || boundary.len() > 70 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "multipart boundary must be 1 to 70 characters"));
+        }
+        // The body starts with the delimiter minus its line break, so pretend there was one.
+        Ok(Self {
+            reader: body,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buf: b"\r\n".to_vec(),
+            eof: false,
+            done: false,
+            parts: 0,
+            limits: MultipartLimits::default(),
+            temp_dir: std::env::temp_dir(),
+        })
+    }
+
+    pub fn limits(mut self, limits: MultipartLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Where large file parts are written; default [`std::env::temp_dir`].
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// The next part, or `None` after the closing boundary.
+    pub fn next_part(&mut self) -> io::Result<Option<Part>> {
+        if self.done {
+            return Ok(None);
+        }
+        if self.parts == 0 {
+            // Skip the preamble.
+            self.copy_until_delimiter(&mut io::sink(), u64::MAX)?;
+        }
+        if self.after_delimiter()? {
+            self.done = true;
+            return Ok(None);
+        }
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(invalid("too many multipart parts"));
+        }
+
+        let headers = self.read_headers()?;
+        let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+        let disposition = header("content-disposition").ok_or_else(|| invalid("multipart part without Content-Disposition"))?;
+        let name = disposition_param(disposition, "name").ok_or_else(|| invalid("multipart part without a name"))?;
+        let filename = disposition_param(disposition, "filename");
+        let content_type = header("content-type").map(str::to_string);
+
+        let data = if filename.is_some() {
+            let dir = self.temp_dir.clone();
+            let mut sink = Spill::new(self.limits.memory_threshold, &dir);
+            self.copy_until_delimiter(&mut sink, self.limits.max_file_bytes)?;
+            sink.finish()?
+        } else {
+            let mut data = Vec::new();
+            self.copy_until_delimiter(&mut data, self.limits.max_field_bytes)?;
+            PartData::Memory(data)
+        };
+        Ok(Some(Part { name, filename, content_type, headers, data }))
+    }
+
+    /// Read more input into the buffer, noting end of input.
+    fn fill(&mut self) -> io::Result<()> {
+        let start = self.buf.len();
+        self.buf.resize(start + READ_SIZE, 0);
+        let n = loop {
+            match self.reader.read(&mut self.buf[start..]) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(e);
+                }
+            }
+        };
+        self.buf.truncate(start + n);
+        self.eof = n == 0;
+        Ok(())
+    }
+
+    /// Write everything up to the next delimiter to `out` and consume the delimiter.
+    fn copy_until_delimiter(&mut self, out: &mut dyn Write, limit: u64) -> io::Result<()> {
+        let mut written = 0u64;
+        loop {
+            let found = self.buf.windows(self.delimiter.len()).position(|w| w == self.delimiter);
+            // Without a match, hold back enough bytes to catch a delimiter split across reads.
+            let end = found.unwrap_or_else(|| self.buf.len().saturating_sub(self.delimiter.len() - 1));
+            written += end as u64;
+            if written > limit {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, BodyTooLarge { limit }));
+            }
+            out.write_all(&self.buf[..end])?;
+            self.buf.drain(..end);
+            if found.is_some() {
+                self.buf.drain(..self.delimiter.len());
+                return Ok(());
+            }
+            if self.eof {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body ended before its closing boundary"));
+            }
+            self.fill()?;
+        }
+    }
+
+    /// After a delimiter: `--` closes the body, otherwise the line ends and a part follows.
+    fn after_delimiter(&mut self) -> io::Result<bool> {
+        loop {
+            if self.buf.starts_with(b"--") {
+                return Ok(true);
+            }
+            // Transport padding is allowed before the line break.
+            let padding = self.buf.iter().take_while(|b| matches!(b, b' ' | b'\t')).count();
+            if self.buf.len() >= padding + 2 {
+                if &self.buf[padding..padding + 2] != b"\r\n" {
+                    return Err(invalid("malformed multipart boundary line"));
+                }
+                self.buf.drain(..padding + 2);
+                return Ok(false);
+            }
+            if self.eof {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body ended after a boundary"));
+            }
+            self.fill()?;
+        }
+    }
+
+    fn read_headers(&mut self) -> io::Result<Vec<(String, String)>> {
+        let end = loop {
+            // A part with no headers starts with the blank line right away.
+            if self.buf.starts_with(b"\r\n") {
+                break 0;
+            }
+            if let Some(pos) = self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 2;
+            }
+            if self.buf.len() > self.limits.max_header_bytes {
+                return Err(invalid("multipart part headers too large"));
+            }
+            if self.eof {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body ended inside part headers"));
+            }
+            self.fill()?;
+        };
+        let head = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+        self.buf.drain(..end + 2);
+        head.split("\r\n")
+            .filter(|line| !line.is_empty())
+            .map(|line| line.split_once(':').map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string())).ok_or_else(|| invalid("malformed multipart part header")))
+            .collect()
+    }
+}
+
+/// A parameter of a `Content-Disposition` value, e.g. `name` in `form-data; name="title"`.
+fn disposition_param(value: &str, param: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        let (name, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (_, '\\') => value.push(chars.next()?.1),
+                        (i, '"') => break i + 1,
+                        (_, c) => value.push(c),
+                    }
+                };
+                (value, quoted[end..].split_once(';').map_or("", |(_, next)| next))
+            }
+            None => {
+                let (value, next) = after.split_once(';').unwrap_or((after, ""));
+                (value.trim().to_string(), next)
+            }
+        };
+        if name.trim().eq_ignore_ascii_case(param) {
+            return Some(value);
+        }
+        rest = next;
+    }
+}
+
+/// Collects a part in memory, moving it to a temporary file once it passes `threshold`.
+struct Spill<'d> {
+    memory: Vec<u8>,
+    file: Option<(File, TempFile)>,
+    threshold: usize,
+    dir: &'d Path,
+}
+
+impl<'d> Spill<'d> {
+    fn new(threshold: usize, dir: &'d Path) -> Self {
+        Self { memory: Vec::new(), file: None, threshold, dir }
+    }
+
+    fn finish(self) -> io::Result<PartData> {
+        match self.file {
+            Some((mut file, temp)) => {
+                file.flush()?;
+                Ok(PartData::File(temp))
+            }
+            None => Ok(PartData::Memory(self.memory)),
+        }
+    }
+}
+
+impl Write for Spill<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > self.threshold {
+            let path = self.dir.join(format!("rusthttp-upload-{}", generate_request_id()));
+            let mut options = File::options();
+            options.write(true).create_new(true);
+            // Uploads may be private; the temporary directory is usually shared.
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&path)?;
+            let mut temp = TempFile { path, len: 0 };
+            let spilled = std::mem::take(&mut self.memory);
+            file.write_all(&spilled)?;
+            temp.len = spilled.len() as u64;
+            self.file = Some((file, temp));
+        }
+        match &mut self.file {
+            Some((file, temp)) => {
+                file.write_all(buf)?;
+                temp.len += buf.len() as u64;
+            }
+            None => self.memory.extend_from_slice(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urlencoded_forms_decode() {
+        let form = Form::parse(b"user=ann+lee&tag=a&tag=b%2Fc&empty=&flag&bad=%zz%4&utf=%C3%A9");
+        assert_eq!(form.get("user"), Some("ann lee"));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b/c"]);
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("bad"), Some("%zz%4"));
+        assert_eq!(form.get("utf"), Some("é"));
+        assert_eq!(form.get("missing"), None);
+    }
+
+    #[test]
+    fn multipart_parts_stream_and_spill_to_disk() {
+        /// Hands out a few bytes per read, to split boundaries across reads.
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(7);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let upload = "x".repeat(300);
+        let body = format!(
+            "preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n--XyZ  \r\n\
+             Content-Disposition: form-data; name=\"note\"\r\n\r\nline\r\n--Xy\r\n--XyZ--\r\nepilogue",
+            upload
+        );
+        let mut req = Request::new("POST", "/upload");
+        req.headers.insert("content-type".to_string(), "multipart/form-data; boundary=\"XyZ\"".to_string());
+        let limits = MultipartLimits { memory_threshold: 100, ..MultipartLimits::default() };
+        let mut parts = Multipart::from_request(&req, Trickle(body.as_bytes())).unwrap().limits(limits);
+
+        let title = parts.next_part().unwrap().unwrap();
+        assert_eq!((title.name.as_str(), title.filename.as_deref(), title.text().unwrap().as_str()), ("title", None, "hello"));
+        assert!(title.temp_path().is_none());
+        let file = parts.next_part().unwrap().unwrap();
+        assert_eq!(file.filename.as_deref(), Some("a \"b\".txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(file.len(), 300);
+        let temp = file.temp_path().unwrap().to_path_buf();
+        assert_eq!(std::fs::read_to_string(&temp).unwrap(), upload);
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&temp).unwrap().permissions()) & 0o777, 0o600);
+        drop(file);
+        assert!(!temp.exists());
+        let note = parts.next_part().unwrap().unwrap();
+        assert_eq!(note.text().unwrap(), "line\r\n--Xy");
+        assert!(parts.next_part().unwrap().is_none());
+
+        let small = MultipartLimits { max_field_bytes: 4, ..MultipartLimits::default() };
+        let err = Multipart::new(body.as_bytes(), "XyZ").unwrap().limits(small).next_part().unwrap_err();
+        assert!(crate::body::is_too_large(&err), "{}", err);
+        let one = MultipartLimits { max_parts: 1, ..MultipartLimits::default() };
+        let mut parts = Multipart::new(body.as_bytes(), "XyZ").unwrap().limits(one);
+        assert!(parts.next_part().is_ok());
+        assert_eq!(parts.next_part().unwrap_err().to_string(), "too many multipart parts");
+        let truncated = Multipart::new(&b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end"[..], "XyZ").unwrap().next_part().unwrap_err();
+        assert_eq!(truncated.kind(), std::io::ErrorKind::UnexpectedEof);
+        req.headers.insert("content-type".to_string(), "application/json".to_string());
+        assert_eq!(Multipart::from_request(&req, &b""[..]).err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
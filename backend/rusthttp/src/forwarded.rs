@@ -0,0 +1,162 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::request::Request;
+
+/// An address range in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> io::Result<Cidr> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid address or CIDR range: {}", s));
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn mask_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    let rest = prefix % 8;
+    if a[..full] != b[..full] {
+        return false;
+    }
+    rest == 0 || (a[full] ^ b[full]) & (0xffu8 << (8 - rest)) == 0
+}
+
+/// Treat IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) as IPv4.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// Proxies whose `Forwarded` / `X-Forwarded-For` headers are believed.
+///
+/// Without trusted proxies the client IP is always the peer address. When the peer is trusted,
+/// the forwarding chain is walked from the nearest hop outwards and the first untrusted address
+/// is taken as the client, so a client cannot spoof its address by sending its own header.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    /// Trust nothing; forwarding headers are ignored.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Trust proxies on the loopback interface (`127.0.0.0/8` and `::1`).
+    pub fn loopback() -> Self {
+        Self::parse(&["127.0.0.0/8", "::1"]).expect("valid loopback ranges")
+    }
+
+    /// Trust the given addresses or CIDR ranges.
+    pub fn parse(ranges: &[&str]) -> io::Result<Self> {
+        Ok(Self { ranges: ranges.iter().map(|r| Cidr::parse(r)).collect::<io::Result<_>>()? })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    /// Resolve the originating client address of `req`, which arrived from `peer`.
+    ///
+    /// Prefers the standard `Forwarded` header over `X-Forwarded-For`. Unparseable entries
+    /// (obfuscated identifiers, `unknown`) end the walk at the last trusted hop.
+    pub fn client_ip(&self, peer: IpAddr, req: &Request) -> IpAddr {
+        let peer = canonical(peer);
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        let chain: Vec<Option<IpAddr>> = if let Some(forwarded) = req.header("forwarded") {
+            forwarded_for(forwarded)
+        } else if let Some(xff) = req.header("x-forwarded-for") {
+            xff.split(',').map(|hop| parse_node(hop.trim())).collect()
+        } else {
+            return peer;
+        };
+
+        let mut client = peer;
+        for hop in chain.into_iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = canonical(ip);
+                    if !self.is_trusted(client) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+/// The `for=` parameter of each element of a `Forwarded` header (RFC 7239).
+fn forwarded_for(value: &str) -> Vec<Option<IpAddr>> {
+    value
+        .split(',')
+        .map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then(|| parse_node(value.trim().trim_matches('"')))?
+            })
+        })
+        .collect()
+}
+
+/// Parse a forwarded node: `1.2.3.4`, `1.2.3.4:80`, `[::1]`, `[::1]:80` or a bare IPv6 address.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusted_proxies_resolve_client_ip() {
+        let proxies = TrustedProxies::parse(&["10.0.0.0/8", "::1"]).unwrap();
+        let peer: std::net::IpAddr = "10.1.2.3".parse().unwrap();
+
+        let mut req = Request::new("GET", "/");
+        req.headers.insert("x-forwarded-for".into(), "198.51.100.7, 203.0.113.9, 10.0.0.5".into());
+        assert_eq!(proxies.client_ip(peer, &req).to_string(), "203.0.113.9");
+
+        let mut req = Request::new("GET", "/");
+        req.headers.insert("forwarded".into(), "for=192.0.2.60;proto=http, for=\"[2001:db8::1]:4711\"".into());
+        assert_eq!(proxies.client_ip(peer, &req).to_string(), "2001:db8::1");
+
+        let untrusted: std::net::IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(proxies.client_ip(untrusted, &req), untrusted);
+    }
+}
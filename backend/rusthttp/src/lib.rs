@@ -1,8 +1,9 @@
-//! rusthttp — a tiny, dependency-free HTTP server crate intended for small apps and examples.
+//! rusthttp — a tiny HTTP server crate intended for small apps and examples, with no required
+//! dependencies; TLS and request body decompression are optional features.
 //!
 //! # Examples
 //!
-//! ```rust
+//! ```rust,no_run
 //! use rusthttp::{Router, Method, serve};
 //!
 //! fn main() -> std::io::Result<()> {
@@ -16,15 +17,58 @@
 //! }
 //! ```
 
+pub mod access_log;
+pub mod body;
+pub mod cache;
+pub mod client;
+pub mod conditional;
+pub mod date;
+#[cfg(feature = "compression")]
+mod encoding;
+pub mod error;
+pub mod form;
+pub mod forwarded;
+mod json;
+pub mod listener;
+pub mod metrics;
+pub mod middleware;
+pub mod negotiation;
+pub mod openapi;
+pub mod proxy;
+pub mod rate_limit;
+pub mod record;
 pub mod request;
 pub mod response;
 pub mod router;
+pub mod security;
 pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(test)]
+mod testing;
+mod tree;
 
-pub use request::{Request, parse_http_request};
-pub use response::write_response;
-pub use router::{Method, Router};
-pub use server::serve;
+pub use access_log::{AccessLog, AccessLogEntry, AccessLogger, LogFormat, RotatingFile};
+pub use body::Body;
+pub use cache::ResponseCache;
+pub use client::{Client, ClientRequest, ClientResponse};
+pub use conditional::ConditionalGet;
+pub use error::{ErrorHandler, HttpError, JsonErrors, ProblemJson};
+pub use form::{Form, Multipart};
+pub use forwarded::TrustedProxies;
+pub use listener::Listener;
+pub use metrics::Metrics;
+pub use middleware::{Middleware, Next};
+pub use proxy::Proxy;
+pub use rate_limit::RateLimiter;
+pub use record::Recorder;
+pub use request::{ConnectionInfo, RawRequest, Request, RequestParser, TlsInfo, parse_http_request};
+pub use response::{write_response, write_response_with_headers};
+pub use router::{Method, Route, Router};
+pub use security::{Csrf, SecurityHeaders};
+pub use server::{RouterHandle, Server, serve};
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
 
 #[cfg(test)]
 mod tests {
@@ -66,10 +110,11 @@ mod tests {
             write_response(stream, 200, "OK", "text/plain", b"ok")
         });
 
-        let req = Request { method: "GET".into(), path: "/x".into(), headers: std::collections::HashMap::new(), body: Vec::new() };
+        let req = Request::new("GET", "/x");
         let mut out = Vec::new();
         router.handle(req, &mut out).unwrap();
         let s = String::from_utf8_lossy(&out);
         assert!(s.contains("200 OK"));
     }
+
 }
@@ -0,0 +1,294 @@
+//! Sockets a [`Server`](crate::Server) accepts connections on: TCP, Unix domain sockets, and
+//! sockets inherited through systemd socket activation.
+
+use std::fmt;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+/// A bound socket ready to accept connections.
+///
+/// A Unix socket bound with [`UnixSocket::bind`] is removed again when the listener is dropped.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+        /// Whether we created the socket file and should remove it.
+        owned: bool,
+    },
+}
+
+/// An accepted connection.
+pub(crate) enum Accepted {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Bind `addr`: `unix:/path/to.sock` for a Unix domain socket with default settings,
+    /// otherwise a TCP `host:port`.
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return UnixSocket::new(path).bind();
+            #[cfg(not(unix))]
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("Unix domain sockets are not supported here: {}", path)));
+        }
+        TcpListener::bind(addr).map(Listener::Tcp)
+    }
+
+    /// Listeners passed in by systemd socket activation (`LISTEN_PID` and `LISTEN_FDS`, see
+    /// `sd_listen_fds(3)`), in order. Empty when the process was not socket-activated.
+    ///
+    /// Like `sd_listen_fds(1)`, this removes `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`
+    /// from the environment and marks the sockets close-on-exec, so child processes inherit
+    /// neither.
+    ///
+    /// # Safety
+    ///
+    /// Call it at most once, while the process has a single thread: it modifies the environment
+    /// (see [`std::env::remove_var`]), and the returned listeners take ownership of the inherited
+    /// descriptors, so nothing else may use or close them.
+    #[cfg(unix)]
+    pub unsafe fn from_systemd() -> io::Result<Vec<Listener>> {
+        use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+
+        /// First inherited descriptor, after stdin, stdout and stderr.
+        const SD_LISTEN_FDS_START: RawFd = 3;
+
+        let for_us = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+        let count = std::env::var("LISTEN_FDS");
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            if std::env::var_os(name).is_some() {
+                // SAFETY: the caller guarantees no other thread reads the environment.
+                unsafe { std::env::remove_var(name) };
+            }
+        }
+        let count = match count {
+            Ok(count) if for_us => count.parse::<RawFd>().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("bad LISTEN_FDS: {}", count)))?,
+            _ => return Ok(Vec::new()),
+        };
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            .map(|fd| {
+                // SAFETY: systemd hands these descriptors to this process, and LISTEN_PID
+                // confirms they are meant for it; nothing else in the process owns them.
+                let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+                // Inherited descriptors are not close-on-exec; std duplicates with
+                // `F_DUPFD_CLOEXEC`, and dropping the original closes it.
+                if tcp.local_addr().is_ok() {
+                    return Ok(Listener::Tcp(tcp.try_clone()?));
+                }
+                // SAFETY: as above; ownership passes straight from the TCP wrapper.
+                let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+                let path = unix.local_addr()?.as_pathname().map(Path::to_path_buf).unwrap_or_default();
+                Ok(Listener::Unix { listener: unix.try_clone()?, path, owned: false })
+            })
+            .collect()
+    }
+
+    /// Socket activation is only available on Unix.
+    ///
+    /// # Safety
+    ///
+    /// Always safe here; `unsafe` matches the Unix version.
+    #[cfg(not(unix))]
+    pub unsafe fn from_systemd() -> io::Result<Vec<Listener>> {
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<Accepted> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Accepted::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => listener.accept().map(|(stream, _)| Accepted::Unix(stream)),
+        }
+    }
+
+    /// The socket path of a Unix listener.
+    #[cfg(unix)]
+    pub(crate) fn unix_path(&self) -> Option<&Path> {
+        match self {
+            Listener::Tcp(_) => None,
+            Listener::Unix { path, .. } => Some(path),
+        }
+    }
+}
+
+/// `127.0.0.1:8080` or `unix:/run/app.sock`.
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => f.write_str("tcp socket"),
+            },
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, owned: true, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Settings for binding a Unix domain socket, e.g. one shared with a local reverse proxy:
+///
+/// ```rust,no_run
+/// use rusthttp::listener::UnixSocket;
+///
+/// let listener = UnixSocket::new("/run/notes/http.sock").mode(0o660).group(33).bind()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// A socket file left behind by a process that is no longer listening is replaced; binding fails
+/// with `AddrInUse` if another process still accepts on it, and with `AlreadyExists` if the path
+/// is some other kind of file.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct UnixSocket {
+    path: PathBuf,
+    mode: Option<u32>,
+    owner: Option<u32>,
+    group: Option<u32>,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), mode: None, owner: None, group: None }
+    }
+
+    /// Permission bits for the socket file, e.g. `0o660` so only the owner and group (such as
+    /// the proxy's) can connect. Defaults to what the process umask allows.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Change the socket file's owning user id (needs the privilege to do so).
+    pub fn owner(mut self, uid: u32) -> Self {
+        self.owner = Some(uid);
+        self
+    }
+
+    /// Change the socket file's group id.
+    pub fn group(mut self, gid: u32) -> Self {
+        self.group = Some(gid);
+        self
+    }
+
+    pub fn bind(self) -> io::Result<Listener> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::symlink_metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                if UnixStream::connect(&self.path).is_ok() {
+                    return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use by another process", self.path.display())));
+                }
+                std::fs::remove_file(&self.path)?;
+            }
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", self.path.display()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        if self.mode.is_none() && self.owner.is_none() && self.group.is_none() {
+            return Ok(Listener::Unix { listener: UnixListener::bind(&self.path)?, path: self.path, owned: true });
+        }
+        // A socket gets the umask's permissions when it is bound. Binding it in a directory only
+        // we can enter and moving it into place once its mode and owner are set leaves no window
+        // in which others could connect.
+        let dir = self.private_dir()?;
+        let result = self.bind_in(&dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    /// A new directory next to the socket path, so the socket can be renamed into place.
+    fn private_dir(&self) -> io::Result<PathBuf> {
+        use std::os::unix::fs::DirBuilderExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let parent = self.path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        // Short, since socket paths are limited to about 100 bytes.
+        let dir = parent.join(format!(".{}.{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        Ok(dir)
+    }
+
+    fn bind_in(&self, dir: &Path) -> io::Result<Listener> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let staged = dir.join("s");
+        let listener = UnixListener::bind(&staged)?;
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+        }
+        if self.owner.is_some() || self.group.is_some() {
+            std::os::unix::fs::chown(&staged, self.owner, self.group)?;
+        }
+        std::fs::rename(&staged, &self.path)?;
+        Ok(Listener::Unix { listener, path: self.path.clone(), owned: true })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn server_listens_on_tcp_and_unix_sockets() {
+        use crate::{Client, Method, Router, Server, write_response};
+        use std::io::{Read, Write};
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let dir = std::env::temp_dir().join(format!("rusthttp-unix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("http.sock");
+        // A socket file left behind by a process that exited.
+        drop(UnixListener::bind(&path).unwrap());
+        let unix = UnixSocket::new(&path).mode(0o600).bind().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        // The directory the socket was bound in is gone.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(UnixSocket::new(&path).bind().err().unwrap().kind(), std::io::ErrorKind::AddrInUse);
+        assert_eq!(UnixSocket::new(dir.join("..").join(dir.file_name().unwrap())).bind().err().unwrap().kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(unix.to_string(), format!("unix:{}", path.display()));
+
+        let tcp = Listener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp.to_string();
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/who", |req, stream| {
+            let body = format!("{:?} {:?}", req.client_ip, req.connection.unix_socket.is_some());
+            write_response(stream, 200, "OK", "text/plain", body.as_bytes())
+        });
+        std::thread::spawn(move || Server::new(router).serve_on(vec![tcp, unix]));
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"GET /who HTTP/1.1\r\nHost: local\r\n\r\n").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.ends_with("\r\n\r\nSome(127.0.0.1) true"), "{}", out);
+        assert_eq!(Client::new().get(&format!("http://{}/who", tcp_addr)).unwrap().text(), "Some(127.0.0.1) false");
+
+        // SAFETY: the test process is not socket-activated, so there is no LISTEN_* variable to
+        // remove and no descriptor to take.
+        assert!(unsafe { Listener::from_systemd() }.unwrap().is_empty());
+        assert_eq!(Server::new(Router::new()).serve_on(Vec::new()).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
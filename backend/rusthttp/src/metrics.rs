@@ -0,0 +1,176 @@
+//! Request counters, latency histograms and connection gauges in Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::request::Request;
+use crate::response::write_response;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Route label for requests that matched no route, so unknown paths cannot grow the label set.
+pub const UNMATCHED: &str = "unmatched";
+
+#[derive(Default)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+    deadlines_exceeded: u64,
+}
+
+/// Server-wide metrics, shared between the [`Server`](crate::Server) that records them and the
+/// handler that renders them.
+///
+/// Requests are keyed by method and route pattern (e.g. `/api/notes/*`), never the raw path.
+#[derive(Default)]
+pub struct Metrics {
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+    in_flight: AtomicI64,
+    connections_open: AtomicI64,
+    connections_total: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a finished request.
+    pub fn observe(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let mut routes = self.routes.lock().unwrap();
+        let stats = routes.entry((method.to_string(), route.to_string())).or_default();
+        *stats.statuses.entry(status).or_default() += 1;
+        let secs = latency.as_secs_f64();
+        for (bucket, bound) in stats.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        stats.sum += secs;
+        stats.count += 1;
+    }
+
+    /// Record a request that outlived its route's [deadline](crate::Route::deadline).
+    pub(crate) fn deadline_exceeded(&self, method: &str, route: &str) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.entry((method.to_string(), route.to_string())).or_default().deadlines_exceeded += 1;
+    }
+
+    pub(crate) fn request_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_finished(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_opened(&self) {
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+        self.connections_open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        self.connections_open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let routes = self.routes.lock().unwrap();
+
+        out.push_str("# HELP http_requests_total Requests handled, by method, route pattern and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route), stats) in routes.iter() {
+            for (status, count) in &stats.statuses {
+                let _ = writeln!(out, "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}", escape(method), escape(route), status, count);
+            }
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Time from request head to response, by method and route pattern.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), stats) in routes.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            for (bound, count) in BUCKETS.iter().zip(stats.buckets) {
+                let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+            }
+            let _ = writeln!(out, "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, stats.count);
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{}}} {}", labels, stats.sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{}}} {}", labels, stats.count);
+        }
+
+        out.push_str("# HELP http_deadlines_exceeded_total Requests that outlived their route's deadline, by method and route pattern.\n");
+        out.push_str("# TYPE http_deadlines_exceeded_total counter\n");
+        for ((method, route), stats) in routes.iter().filter(|(_, stats)| stats.deadlines_exceeded > 0) {
+            let _ = writeln!(out, "http_deadlines_exceeded_total{{method=\"{}\",route=\"{}\"}} {}", escape(method), escape(route), stats.deadlines_exceeded);
+        }
+
+        let gauges = [
+            ("http_requests_in_flight", "gauge", "Requests currently being handled.", self.in_flight.load(Ordering::Relaxed)),
+            ("http_connections_open", "gauge", "Connections currently open.", self.connections_open.load(Ordering::Relaxed)),
+            ("http_connections_total", "counter", "Connections accepted.", self.connections_total.load(Ordering::Relaxed) as i64),
+        ];
+        for (name, kind, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        }
+        out
+    }
+
+    /// A handler serving [`render`](Self::render), for registering at e.g. `/metrics`.
+    pub fn handler(self: &Arc<Self>) -> impl Fn(&Request, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static {
+        let metrics = Arc::clone(self);
+        move |_req, stream| write_response(stream, 200, "OK", "text/plain; version=0.0.4", metrics.render().as_bytes())
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect_server;
+    use crate::{Method, Router, Server};
+
+    #[test]
+    fn metrics_keyed_by_route_pattern() {
+        use std::io::{Read, Write};
+        use std::sync::Arc;
+        let metrics = Arc::new(Metrics::new());
+        for path in ["/notes/1", "/notes/2", "/missing"] {
+            let mut router = Router::new();
+            router.add_prefix_route(Method::Get, "/notes/", |_req, stream| write_response(stream, 200, "OK", "text/plain", b"note"));
+            let mut client = connect_server(Server::new(router).metrics(Arc::clone(&metrics)));
+            client.write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes()).unwrap();
+            client.read_to_end(&mut Vec::new()).unwrap();
+        }
+
+        // Connections are counted closed just after the socket is dropped.
+        let mut text = String::new();
+        for _ in 0..100 {
+            text = metrics.render();
+            if text.contains("http_connections_open 0\n") {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(text.contains("http_requests_total{method=\"GET\",route=\"/notes/*\",status=\"200\"} 2\n"), "{}", text);
+        assert!(text.contains("http_requests_total{method=\"-\",route=\"unmatched\",status=\"404\"} 1\n"), "{}", text);
+        assert!(text.contains("http_request_duration_seconds_count{method=\"GET\",route=\"/notes/*\"} 2\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/notes/*\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("http_connections_total 3\n"));
+        assert!(text.contains("http_connections_open 0\n"));
+        assert!(text.contains("http_requests_in_flight 0\n"));
+
+        let mut out = Vec::new();
+        metrics.handler()(&Request::new("GET", "/metrics"), &mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains("Content-Type: text/plain; version=0.0.4"));
+    }
+}
@@ -0,0 +1,90 @@
+//! Code that runs around route handlers.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::request::Request;
+
+/// Runs around a handler: inspect the request, answer it directly, or pass it on with
+/// [`Next::run`] (possibly with a modified copy of the request or a wrapped writer).
+///
+/// Attach to one route with [`Route::wrap`](crate::Route::wrap) or to every request with
+/// [`Router::wrap`](crate::Router::wrap). Implemented for closures taking the same arguments.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&Request, &mut dyn Write, Next<'_>) -> io::Result<()> + Send + Sync,
+{
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        self(req, stream, next)
+    }
+}
+
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        (**self).handle(req, stream, next)
+    }
+}
+
+pub(crate) type Endpoint<'a> = &'a mut dyn FnMut(&Request, &mut dyn Write) -> io::Result<()>;
+
+/// The rest of the middleware chain, ending in the handler.
+pub struct Next<'a> {
+    current: &'a [Arc<dyn Middleware>],
+    layers: &'a [&'a [Arc<dyn Middleware>]],
+    endpoint: Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+    /// Chain each layer in turn (e.g. router, then route), ending at `endpoint`.
+    pub(crate) fn new(layers: &'a [&'a [Arc<dyn Middleware>]], endpoint: Endpoint<'a>) -> Self {
+        Self { current: &[], layers, endpoint }
+    }
+
+    /// Run the remaining middleware and the handler.
+    pub fn run(mut self, req: &Request, stream: &mut dyn Write) -> io::Result<()> {
+        loop {
+            if let Some((m, current)) = self.current.split_first() {
+                return m.handle(req, stream, Next { current, layers: self.layers, endpoint: self.endpoint });
+            }
+            match self.layers.split_first() {
+                Some((layer, layers)) => (self.current, self.layers) = (layer, layers),
+                None => return (self.endpoint)(req, stream),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router};
+
+    #[test]
+    fn middleware_runs_router_then_route_in_order() {
+        let mut router = Router::new();
+        router.wrap(|req: &Request, stream: &mut dyn std::io::Write, next: Next<'_>| {
+            stream.write_all(b"[outer]")?;
+            next.run(req, stream)
+        });
+        router
+            .add_route(Method::Get, "/x", |_req, stream| stream.write_all(b"handler"))
+            .wrap(|req: &Request, stream: &mut dyn std::io::Write, next: Next<'_>| {
+                stream.write_all(b"[inner]")?;
+                next.run(req, stream)
+            })
+            .wrap(|_req: &Request, stream: &mut dyn std::io::Write, _next: Next<'_>| stream.write_all(b"blocked"));
+
+        let mut out = Vec::new();
+        router.handle(Request::new("GET", "/x"), &mut out).unwrap();
+        assert_eq!(out, b"[outer][inner]blocked");
+
+        // Router-level middleware also sees unrouted requests.
+        let mut out = Vec::new();
+        router.handle(Request::new("GET", "/missing"), &mut out).unwrap();
+        assert!(String::from_utf8_lossy(&out).starts_with("[outer]HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Content negotiation: matching `Content-Type` against accepted media types and choosing a
+//! response media type from `Accept` (RFC 9110 section 12).
+//!
+//! Routes declare both with [`Route::accepts`](crate::Route::accepts) and
+//! [`Route::produces`](crate::Route::produces); these functions are for handlers that negotiate
+//! for themselves.
+
+/// The media type of a header value without parameters, lower-cased: `Text/HTML; charset=utf-8`
+/// gives `text/html`.
+pub fn essence(value: &str) -> String {
+    value.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Whether `media_type` falls within `range`, which may be `type/*` or `*/*`.
+pub fn media_type_matches(range: &str, media_type: &str) -> bool {
+    let (range, media_type) = (essence(range), essence(media_type));
+    match range.split_once('/') {
+        Some(("*", "*")) => true,
+        Some((kind, "*")) => media_type.split_once('/').is_some_and(|(t, _)| t == kind),
+        _ => range == media_type,
+    }
+}
+
+/// The entry of `offered` the client prefers according to `accept`, or `None` if it accepts
+/// none of them.
+///
+/// Each offered type takes the quality of the most specific range matching it (`text/html` over
+/// `text/*` over `*/*`). Ties go to the type offered first, so list the server's preference
+/// first. Without an `Accept` header anything is acceptable and the first type is chosen.
+pub fn preferred_type<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = accept else {
+        return offered.first().copied();
+    };
+    let ranges: Vec<(String, f32)> = accept
+        .split(',')
+        .filter(|range| !range.trim().is_empty())
+        .map(|range| {
+            let mut params = range.split(';');
+            let media_range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let q = params.filter_map(|p| p.split_once('=')).find(|(name, _)| name.trim().eq_ignore_ascii_case("q")).and_then(|(_, q)| q.trim().parse::<f32>().ok()).unwrap_or(1.0);
+            (media_range, q.clamp(0.0, 1.0))
+        })
+        .collect();
+
+    let quality = |media_type: &str| {
+        let specificity = |range: &str| match range.split_once('/') {
+            Some(("*", "*")) => 0,
+            Some((_, "*")) => 1,
+            _ => 2,
+        };
+        ranges.iter().filter(|(range, _)| media_type_matches(range, media_type)).max_by_key(|(range, _)| specificity(range)).map_or(0.0, |(_, q)| *q)
+    };
+    let mut best: Option<(&'a str, f32)> = None;
+    for media_type in offered {
+        let q = quality(media_type);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((media_type, q));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Request, Router, write_response};
+
+    #[test]
+    fn preferred_type_weighs_quality_and_specificity() {
+        let offered = ["text/plain", "application/json"];
+        assert_eq!(preferred_type(None, &offered), Some("text/plain"));
+        assert_eq!(preferred_type(Some("application/json"), &offered), Some("application/json"));
+        assert_eq!(preferred_type(Some("text/*;q=0.5, application/json;q=0.9"), &offered), Some("application/json"));
+        // The specific range overrides the wildcard, even with a lower quality.
+        assert_eq!(preferred_type(Some("*/*, text/plain;q=0.1"), &offered), Some("application/json"));
+        // Equal qualities keep the server's order.
+        assert_eq!(preferred_type(Some("application/json, text/plain"), &offered), Some("text/plain"));
+        assert_eq!(preferred_type(Some("text/plain;q=0, image/png"), &offered), None);
+    }
+
+    #[test]
+    fn routes_reject_unsupported_and_unacceptable_media_types() {
+        let mut router = Router::new();
+        router.add_route(Method::Post, "/notes", |_req, stream| write_response(stream, 201, "Created", "text/plain", b"ok")).accepts(&["application/json"]);
+        router
+            .add_route(Method::Get, "/notes", |req, stream| write_response(stream, 200, "OK", "text/plain", req.response_type.as_deref().unwrap_or("").as_bytes()))
+            .produces(&["text/plain", "application/json"]);
+
+        let send = |method: &str, headers: &[(&str, &str)]| {
+            let mut req = Request::new(method, "/notes");
+            for (name, value) in headers {
+                req.headers.insert(name.to_string(), value.to_string());
+            }
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(send("POST", &[("content-type", "application/json; charset=utf-8"), ("content-length", "2")]).starts_with("HTTP/1.1 201 "));
+        assert!(send("POST", &[("content-type", "text/xml"), ("content-length", "2")]).starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+        assert!(send("POST", &[("content-length", "2")]).starts_with("HTTP/1.1 415 "));
+        // Without a body there is nothing to check.
+        assert!(send("POST", &[]).starts_with("HTTP/1.1 201 "));
+
+        assert!(send("GET", &[("accept", "application/*")]).ends_with("\r\n\r\napplication/json"));
+        assert!(send("GET", &[]).ends_with("\r\n\r\ntext/plain"));
+        assert!(send("GET", &[("accept", "image/png")]).starts_with("HTTP/1.1 406 Not Acceptable\r\n"));
+    }
+}
@@ -0,0 +1,247 @@
+//! OpenAPI 3.1 descriptions of a router's routes.
+//!
+//! Routes are described with [`Route::summary`](crate::Route::summary),
+//! [`Route::request_schema`](crate::Route::request_schema),
+//! [`Route::response`](crate::Route::response) and [`Route::auth`](crate::Route::auth); their
+//! methods, path parameters, [accepted](crate::Route::accepts) and
+//! [produced](crate::Route::produces) media types and names come from the routes themselves.
+//! Schemas are JSON Schema documents given as JSON text, e.g. from `serde_json::json!`, and are
+//! copied into the document as they are.
+
+use std::fmt::Write as _;
+
+use crate::error::reason_phrase;
+use crate::json;
+use crate::negotiation;
+use crate::response::write_response;
+use crate::router::{Method, Route, Router};
+
+/// What a route declares for its OpenAPI operation.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OperationDoc {
+    pub(crate) summary: Option<String>,
+    pub(crate) request_schema: Option<String>,
+    pub(crate) responses: Vec<(u16, String, Option<String>)>,
+    pub(crate) security: Vec<String>,
+}
+
+/// The document-level parts of an OpenAPI description, rendered together with a router's routes:
+///
+/// ```rust
+/// use rusthttp::{Method, Router, openapi::OpenApi, write_response};
+///
+/// let mut router = Router::new();
+/// router
+///     .add_route(Method::Get, "/notes/:id", |_req, stream| write_response(stream, 200, "OK", "application/json", b"{}"))
+///     .summary("Fetch a note")
+///     .response(200, "The note", Some(r##"{"$ref": "#/components/schemas/Note"}"##))
+///     .auth("bearer");
+/// OpenApi::new("Notes", "1.0.0")
+///     .security_scheme("bearer", r#"{"type": "http", "scheme": "bearer"}"#)
+///     .schema("Note", r#"{"type": "object"}"#)
+///     .mount(&mut router, "/openapi.json");
+/// ```
+///
+/// Prefix routes (patterns ending in an unnamed `*`) cannot be described as OpenAPI paths and
+/// are left out, as are the routes of virtual hosts.
+#[derive(Clone, Debug)]
+pub struct OpenApi {
+    title: String,
+    version: String,
+    description: Option<String>,
+    servers: Vec<String>,
+    security_schemes: Vec<(String, String)>,
+    schemas: Vec<(String, String)>,
+}
+
+impl OpenApi {
+    /// `version` is the version of the API, not of OpenAPI.
+    pub fn new(title: &str, version: &str) -> Self {
+        Self { title: title.to_string(), version: version.to_string(), description: None, servers: Vec::new(), security_schemes: Vec::new(), schemas: Vec::new() }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// A base URL the API is served at, e.g. `https://notes.example.com`.
+    pub fn server(mut self, url: &str) -> Self {
+        self.servers.push(url.to_string());
+        self
+    }
+
+    /// Define a security scheme routes can require with [`Route::auth`](crate::Route::auth),
+    /// e.g. `{"type": "http", "scheme": "bearer"}`.
+    pub fn security_scheme(mut self, name: &str, scheme: &str) -> Self {
+        self.security_schemes.push((name.to_string(), scheme.to_string()));
+        self
+    }
+
+    /// Define a reusable schema, referenced from route schemas as
+    /// `{"$ref": "#/components/schemas/<name>"}`.
+    pub fn schema(mut self, name: &str, schema: &str) -> Self {
+        self.schemas.push((name.to_string(), schema.to_string()));
+        self
+    }
+
+    /// The OpenAPI document for `router`'s routes, as JSON.
+    pub fn render(&self, router: &Router) -> String {
+        let mut paths: Vec<(String, Vec<String>)> = Vec::new();
+        for route in router.routes() {
+            let Some((path, params)) = openapi_path(route.pattern()) else {
+                continue;
+            };
+            let operation = format!("{}:{}", json::string(&route.method().as_str().to_ascii_lowercase()), operation(route, &params));
+            match paths.iter_mut().find(|(p, _)| *p == path) {
+                Some((_, operations)) => operations.push(operation),
+                None => paths.push((path, vec![operation])),
+            }
+        }
+
+        let mut out = String::from("{\"openapi\":\"3.1.0\",\"info\":{");
+        let _ = write!(out, "\"title\":{},\"version\":{}", json::string(&self.title), json::string(&self.version));
+        if let Some(description) = &self.description {
+            let _ = write!(out, ",\"description\":{}", json::string(description));
+        }
+        out.push('}');
+        if !self.servers.is_empty() {
+            let servers: Vec<String> = self.servers.iter().map(|url| format!("{{\"url\":{}}}", json::string(url))).collect();
+            let _ = write!(out, ",\"servers\":[{}]", servers.join(","));
+        }
+        let paths: Vec<String> = paths.iter().map(|(path, operations)| format!("{}:{{{}}}", json::string(path), operations.join(","))).collect();
+        let _ = write!(out, ",\"paths\":{{{}}}", paths.join(","));
+        if !self.schemas.is_empty() || !self.security_schemes.is_empty() {
+            let members = |entries: &[(String, String)]| entries.iter().map(|(name, value)| format!("{}:{}", json::string(name), value)).collect::<Vec<_>>().join(",");
+            let _ = write!(out, ",\"components\":{{\"schemas\":{{{}}},\"securitySchemes\":{{{}}}}}", members(&self.schemas), members(&self.security_schemes));
+        }
+        out.push('}');
+        out
+    }
+
+    /// Serve the document for the routes registered so far at `GET path`. Mount it after the
+    /// routes it should describe.
+    pub fn mount(self, router: &mut Router, path: &str) {
+        let document = self.render(router);
+        router.add_route(Method::Get, path, move |_req, stream| write_response(stream, 200, "OK", "application/json", document.as_bytes()));
+    }
+}
+
+/// `/notes/:id` as `/notes/{id}` plus its parameter names, or `None` for a prefix route.
+fn openapi_path(pattern: &str) -> Option<(String, Vec<&str>)> {
+    let mut params = Vec::new();
+    let mut path = String::new();
+    for segment in pattern.split('/').skip(1) {
+        path.push('/');
+        match segment.strip_prefix(':').or_else(|| segment.strip_prefix('*')) {
+            Some("") => return None,
+            Some(name) => {
+                let _ = write!(path, "{{{}}}", name);
+                params.push(name);
+            }
+            None => path.push_str(segment),
+        }
+    }
+    Some((path, params))
+}
+
+fn operation(route: &Route, params: &[&str]) -> String {
+    let doc = route.doc();
+    let mut members = Vec::new();
+    if let Some(name) = route.route_name() {
+        members.push(format!("\"operationId\":{}", json::string(name)));
+    }
+    if let Some(summary) = &doc.summary {
+        members.push(format!("\"summary\":{}", json::string(summary)));
+    }
+    if !params.is_empty() {
+        let params: Vec<String> = params.iter().map(|name| format!("{{\"name\":{},\"in\":\"path\",\"required\":true,\"schema\":{{\"type\":\"string\"}}}}", json::string(name))).collect();
+        members.push(format!("\"parameters\":[{}]", params.join(",")));
+    }
+    if doc.request_schema.is_some() || !route.accepted_types().is_empty() {
+        let content = content(route.accepted_types(), doc.request_schema.as_deref());
+        members.push(format!("\"requestBody\":{{\"required\":true,\"content\":{}}}", content));
+    }
+
+    let mut responses: Vec<String> = doc
+        .responses
+        .iter()
+        .map(|(status, description, schema)| {
+            let content = schema.as_deref().map(|schema| format!(",\"content\":{}", content(route.produced_types(), Some(schema)))).unwrap_or_default();
+            format!("\"{}\":{{\"description\":{}{}}}", status, json::string(description), content)
+        })
+        .collect();
+    // Answers the router gives on the handler's behalf.
+    for (status, applies) in [(406, !route.produced_types().is_empty()), (415, !route.accepted_types().is_empty())] {
+        if applies && !doc.responses.iter().any(|(s, _, _)| *s == status) {
+            responses.push(format!("\"{}\":{{\"description\":{}}}", status, json::string(reason_phrase(status))));
+        }
+    }
+    if responses.is_empty() {
+        responses.push("\"default\":{\"description\":\"Response\"}".to_string());
+    }
+    members.push(format!("\"responses\":{{{}}}", responses.join(",")));
+
+    if !doc.security.is_empty() {
+        let security: Vec<String> = doc.security.iter().map(|scheme| format!("{{{}:[]}}", json::string(scheme))).collect();
+        members.push(format!("\"security\":[{}]", security.join(",")));
+    }
+    format!("{{{}}}", members.join(","))
+}
+
+/// A content map for `media_types`, or `application/json` if there are none. `schema` describes
+/// the JSON types among them, or all of them when none is JSON.
+fn content(media_types: &[String], schema: Option<&str>) -> String {
+    let json_only = ["application/json".to_string()];
+    let media_types = if media_types.is_empty() { &json_only[..] } else { media_types };
+    let is_json = |media_type: &str| {
+        let essence = negotiation::essence(media_type);
+        essence.ends_with("/json") || essence.ends_with("+json")
+    };
+    let any_json = media_types.iter().any(|media_type| is_json(media_type));
+    let entries: Vec<String> = media_types
+        .iter()
+        .map(|media_type| {
+            let schema = schema.filter(|_| !any_json || is_json(media_type)).map(|schema| format!("\"schema\":{}", schema)).unwrap_or_default();
+            format!("{}:{{{}}}", json::string(media_type), schema)
+        })
+        .collect();
+    format!("{{{}}}", entries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Request;
+
+    #[test]
+    fn openapi_describes_routes() {
+        let mut router = Router::new();
+        router
+            .add_route(Method::Post, "/notes", |_req, stream| write_response(stream, 201, "Created", "application/json", b"{}"))
+            .accepts(&["application/json"])
+            .request_schema(r#"{"type":"object"}"#)
+            .response(201, "Created", Some(r##"{"$ref":"#/components/schemas/Note"}"##))
+            .auth("bearer");
+        router
+            .add_route(Method::Get, "/notes/:id/changes", |_req, stream| write_response(stream, 200, "OK", "text/plain", b""))
+            .name("changes")
+            .summary("A note's \"history\"")
+            .produces(&["text/plain", "application/json"])
+            .response(200, "Changes", Some(r#"{"type":"array"}"#));
+        router.add_route(Method::Get, "/notes", |_req, stream| write_response(stream, 200, "OK", "application/json", b"[]"));
+        router.add_prefix_route(Method::Get, "/static/", |_req, stream| write_response(stream, 200, "OK", "text/plain", b""));
+        OpenApi::new("Notes", "1.0").security_scheme("bearer", r#"{"type":"http","scheme":"bearer"}"#).schema("Note", r#"{"type":"object"}"#).mount(&mut router, "/openapi.json");
+
+        let mut out = Vec::new();
+        router.handle(Request::new("GET", "/openapi.json"), &mut out).unwrap();
+        let doc = String::from_utf8(out).unwrap();
+        let doc = doc.split_once("\r\n\r\n").unwrap().1;
+        assert!(doc.starts_with(r#"{"openapi":"3.1.0","info":{"title":"Notes","version":"1.0"},"paths":{"/notes":{"post":{"#), "{}", doc);
+        assert!(doc.contains(r##""requestBody":{"required":true,"content":{"application/json":{"schema":{"type":"object"}}}},"responses":{"201":{"description":"Created","content":{"application/json":{"schema":{"$ref":"#/components/schemas/Note"}}}},"415":{"description":"Unsupported Media Type"}},"security":[{"bearer":[]}]},"get":{"responses":{"default":{"description":"Response"}}}}"##), "{}", doc);
+        assert!(doc.contains(r#""/notes/{id}/changes":{"get":{"operationId":"changes","summary":"A note's \"history\"","parameters":[{"name":"id","in":"path","required":true,"schema":{"type":"string"}}],"#), "{}", doc);
+        assert!(doc.contains(r#""content":{"text/plain":{},"application/json":{"schema":{"type":"array"}}}},"406":{"description":"Not Acceptable"}"#), "{}", doc);
+        assert!(!doc.contains("/static") && !doc.contains("/openapi.json"));
+        assert!(doc.ends_with(r#""components":{"schemas":{"Note":{"type":"object"}},"securitySchemes":{"bearer":{"type":"http","scheme":"bearer"}}}}"#), "{}", doc);
+    }
+}
@@ -0,0 +1,320 @@
+//! Forwarding requests to an upstream HTTP server.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::body::{Body, Framing};
+use crate::client::parse_status_line;
+use crate::error::HttpError;
+use crate::request::{Request, RequestParser};
+use crate::router::{Method, Router};
+
+/// Default for [`Proxy::timeout`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that describe a single connection and are never forwarded (RFC 9110 section 7.6.1).
+const HOP_BY_HOP: [&str; 9] = ["connection", "keep-alive", "proxy-authenticate", "proxy-authorization", "proxy-connection", "te", "trailer", "transfer-encoding", "upgrade"];
+
+/// A handler that forwards requests to an upstream server and streams its responses back.
+///
+/// Hop-by-hop headers are dropped in both directions, `X-Forwarded-For`, `X-Forwarded-Proto`
+/// and `X-Forwarded-Host` are added, and `Host` is set to the upstream address. If the upstream
+/// cannot be reached or sends a malformed response the client gets `502 Bad Gateway`; if it
+/// does not answer within the timeout, `504 Gateway Timeout`.
+///
+/// ```rust,no_run
+/// use rusthttp::{Router, proxy::Proxy};
+///
+/// let mut router = Router::new();
+/// Proxy::new("127.0.0.1:9000").strip_prefix("/legacy").mount(&mut router, "/legacy/");
+/// ```
+#[derive(Clone)]
+pub struct Proxy {
+    upstream: String,
+    timeout: Duration,
+    strip_prefix: Option<String>,
+}
+
+impl Proxy {
+    /// Forward to `upstream`, a `host:port` address.
+    pub fn new(upstream: &str) -> Self {
+        Self { upstream: upstream.to_string(), timeout: DEFAULT_TIMEOUT, strip_prefix: None }
+    }
+
+    /// Give up with `504` if connecting, or any read or write, takes longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Remove `prefix` from request paths before forwarding, e.g. `/legacy/notes` becomes `/notes`.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        self.strip_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Register streaming prefix routes for every method under `prefix`, all forwarding here.
+    pub fn mount(self, router: &mut Router, prefix: &str) {
+        let proxy = Arc::new(self);
+        for method in Method::ALL {
+            let proxy = Arc::clone(&proxy);
+            router.add_streaming_prefix_route(method, prefix, move |req, body, stream| proxy.forward(req, body, stream)).decode_bodies(false);
+        }
+    }
+
+    /// Forward `req` with its `body`, and write the upstream response to `stream`.
+    pub fn forward(&self, req: &Request, body: &mut Body<'_>, stream: &mut dyn Write) -> io::Result<()> {
+        let mut upstream = match self.connect() {
+            Ok(upstream) => upstream,
+            Err(e) => return self.fail(req, stream, &e),
+        };
+        if let Err(e) = self.send_request(&mut upstream, req, body) {
+            return self.fail(req, stream, &e);
+        }
+
+        let mut parser = RequestParser::new();
+        let (status, reason, headers, raw_headers) = loop {
+            let raw = match parser.read_head(&mut upstream) {
+                Ok(Some(raw)) => raw,
+                Ok(None) => return self.fail(req, stream, &io::Error::new(io::ErrorKind::UnexpectedEof, "upstream closed the connection")),
+                Err(e) => return self.fail(req, stream, &e),
+            };
+            let (status, reason) = match parse_status_line(raw.start_line()) {
+                Ok((_, status, reason)) => (status, reason),
+                Err(e) => return self.fail(req, stream, &e),
+            };
+            // Interim responses were already handled between us and the client.
+            if (100..200).contains(&status) {
+                parser.consume();
+                continue;
+            }
+            let raw_headers: Vec<(String, String)> = raw.headers().map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value).into_owned())).collect();
+            break (status, reason, raw.to_request(Vec::new()), raw_headers);
+        };
+        // Responses to HEAD keep the headers of a GET, but never have a body, whatever their
+        // `Content-Length` says; waiting for one would hang until the upstream gives up.
+        let framing = match (headers.header("transfer-encoding"), headers.header("content-length")) {
+            _ if req.method == "HEAD" || status == 204 || status == 304 => Some(Framing::Length(0)),
+            (None, None) => None,
+            _ => match Framing::from_request(&headers) {
+                Ok(framing) => Some(framing),
+                Err(e) => return self.fail(req, stream, &e),
+            },
+        };
+
+        let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason);
+        let dropped = connection_tokens(&headers);
+        for (name, value) in &raw_headers {
+            let lower = name.to_ascii_lowercase();
+            if !is_hop_by_hop(&lower, &dropped) && (lower != "content-length" || framing != Some(Framing::Chunked)) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        stream.write_all(head.as_bytes())?;
+
+        // The response is re-framed by closing the connection after it, so a chunked upstream
+        // body is passed on decoded.
+        let mut source = parser.body_reader(&mut upstream);
+        match framing {
+            Some(framing) => io::copy(&mut Body::new(&mut source, framing, u64::MAX), stream)?,
+            None => io::copy(&mut source, stream)?,
+        };
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", self.upstream));
+        for addr in self.upstream.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn send_request(&self, upstream: &mut TcpStream, req: &Request, body: &mut Body<'_>) -> io::Result<()> {
+        let path = match self.strip_prefix.as_deref().and_then(|prefix| req.path.strip_prefix(prefix)) {
+            Some(rest) if rest.starts_with('/') => rest.to_string(),
+            Some(rest) => format!("/{}", rest),
+            None => req.path.clone(),
+        };
+        let framing = Framing::from_request(req)?;
+
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", req.method, path, self.upstream);
+        let dropped = connection_tokens(req);
+        for (name, value) in &req.headers {
+            let replaced = matches!(name.as_str(), "host" | "content-length" | "expect" | "x-forwarded-for" | "x-forwarded-proto" | "x-forwarded-host");
+            if !replaced && !is_hop_by_hop(name, &dropped) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if let Some(peer) = req.connection.peer_addr {
+            let forwarded_for = match req.header("x-forwarded-for") {
+                Some(chain) => format!("{}, {}", chain, peer.ip()),
+                None => peer.ip().to_string(),
+            };
+            head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for));
+        }
+        head.push_str(&format!("X-Forwarded-Proto: {}\r\n", if req.connection.is_secure() { "https" } else { "http" }));
+        if let Some(host) = req.header("host") {
+            head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+        }
+        match framing {
+            Framing::Length(len) => head.push_str(&format!("Content-Length: {}\r\n\r\n", len)),
+            Framing::Chunked => head.push_str("Transfer-Encoding: chunked\r\n\r\n"),
+        }
+        upstream.write_all(head.as_bytes())?;
+
+        // Buffered routes have already read the body into `req.body`.
+        if !req.body.is_empty() {
+            upstream.write_all(&req.body)?;
+        } else if framing == Framing::Chunked {
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = body.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                write!(upstream, "{:x}\r\n", n)?;
+                upstream.write_all(&buf[..n])?;
+                upstream.write_all(b"\r\n")?;
+            }
+            upstream.write_all(b"0\r\n\r\n")?;
+        } else {
+            io::copy(body, upstream)?;
+        }
+        upstream.flush()
+    }
+
+    fn fail(&self, req: &Request, stream: &mut dyn Write, err: &io::Error) -> io::Result<()> {
+        eprintln!("proxy to {} failed: {}", self.upstream, err);
+        if matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) {
+            req.send_error(stream, HttpError::new(504, "gateway timeout"))
+        } else {
+            req.send_error(stream, HttpError::new(502, "bad gateway"))
+        }
+    }
+}
+
+/// Header names listed in `Connection`, which are hop-by-hop for this message only.
+fn connection_tokens(message: &Request) -> HashSet<String> {
+    message.header("connection").map(|v| v.split(',').map(|t| t.trim().to_ascii_lowercase()).collect()).unwrap_or_default()
+}
+
+fn is_hop_by_hop(name: &str, listed: &HashSet<String>) -> bool {
+    HOP_BY_HOP.contains(&name) || listed.contains(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Client, Server, server};
+
+    /// Serve `router` on a background thread until the test ends, returning its address.
+    fn spawn_server(router: Router) -> std::net::SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(router);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = server::serve_tcp(stream, &server);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn proxy_forwards_requests_and_streams_responses() {
+        use std::io::Write;
+        let upstream = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let req = RequestParser::new().read_request(&mut stream).unwrap().unwrap();
+            let summary = format!(
+                "{} {} host={} xff={} proto={} fwd-host={} hop={} body={}",
+                req.method,
+                req.path,
+                req.header("host").unwrap_or("-"),
+                req.header("x-forwarded-for").unwrap_or("-"),
+                req.header("x-forwarded-proto").unwrap_or("-"),
+                req.header("x-forwarded-host").unwrap_or("-"),
+                req.header("x-hop").unwrap_or("-"),
+                String::from_utf8_lossy(&req.body)
+            );
+            let chunk = format!("{:x}\r\n{}\r\n0\r\n\r\n", summary.len(), summary);
+            write!(stream, "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\nConnection: x-upstream\r\nX-Upstream: 1\r\nX-Kept: yes\r\n\r\n{}", chunk).unwrap();
+        });
+
+        let mut router = Router::new();
+        Proxy::new(&upstream_addr.to_string()).strip_prefix("/legacy").mount(&mut router, "/legacy/");
+        let addr = spawn_server(router);
+
+        let resp = Client::new()
+            .request(Method::Put, &format!("http://{}/legacy/notes/1?x=y", addr))
+            .header("Connection", "x-hop")
+            .header("X-Hop", "dropped")
+            .body("data")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status, 201);
+        assert_eq!(resp.header("x-kept"), Some("yes"));
+        assert_eq!(resp.header("x-upstream"), None);
+        assert_eq!(resp.header("transfer-encoding"), None);
+        assert_eq!(resp.text(), format!("PUT /notes/1?x=y host={} xff=127.0.0.1 proto=http fwd-host={} hop=- body=data", upstream_addr, addr));
+    }
+
+    #[test]
+    fn proxy_forwards_head_responses_without_a_body() {
+        use std::io::Write;
+        let upstream = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let req = RequestParser::new().read_request(&mut stream).unwrap().unwrap();
+            assert_eq!(req.method, "HEAD");
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n").unwrap();
+            // Keep the connection open, as a keep-alive upstream would.
+            std::thread::sleep(std::time::Duration::from_secs(5));
+        });
+
+        // The router has no HEAD routes, but handlers can forward any request themselves.
+        let proxy = Proxy::new(&upstream_addr.to_string()).timeout(std::time::Duration::from_secs(2));
+        let mut out = Vec::new();
+        let started = std::time::Instant::now();
+        proxy.forward(&Request::new("HEAD", "/file"), &mut Body::new(&mut std::io::empty(), Framing::Length(0), 0), &mut out).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(1), "took {:?}", started.elapsed());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n") && out.contains("\r\nContent-Length: 5\r\n"), "{}", out);
+        assert!(out.ends_with("\r\n\r\n"), "{}", out);
+    }
+
+    #[test]
+    fn proxy_answers_502_and_504_on_upstream_failure() {
+        let unreachable = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _held: Vec<_> = silent.incoming().take(1).collect();
+            std::thread::sleep(std::time::Duration::from_secs(5));
+        });
+
+        let mut router = Router::new();
+        Proxy::new(&unreachable.to_string()).mount(&mut router, "/down/");
+        Proxy::new(&silent_addr.to_string()).timeout(std::time::Duration::from_millis(100)).mount(&mut router, "/slow/");
+        let addr = spawn_server(router);
+
+        let client = Client::new();
+        assert_eq!(client.get(&format!("http://{}/down/x", addr)).unwrap().status, 502);
+        assert_eq!(client.get(&format!("http://{}/slow/x", addr)).unwrap().status, 504);
+    }
+}
@@ -0,0 +1,194 @@
+//! Token-bucket rate limiting, as [`Middleware`].
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::HttpError;
+use crate::middleware::{Middleware, Next};
+use crate::request::Request;
+
+/// Default for [`RateLimiter::max_keys`].
+const DEFAULT_MAX_KEYS: usize = 10_000;
+
+type KeyFn = Box<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of [`RateLimiter::check`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Whole tokens left after this request.
+    pub remaining: u32,
+    /// Time until another token is available; zero if one already is.
+    pub retry_after: Duration,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+}
+
+/// Allows each key a burst of `capacity` requests, refilled at a steady rate.
+///
+/// Requests are keyed by client IP unless [`key_by`](Self::key_by) says otherwise. Rejected
+/// requests get `429 Too Many Requests` with `Retry-After` and `RateLimit-*` headers. Wrap a
+/// route with one limiter for a per-route limit, or share it through an `Arc` across several.
+///
+/// At most [`max_keys`](Self::max_keys) buckets are kept; when a new key arrives at the limit,
+/// buckets that have refilled completely are dropped first, then the least recently used one.
+pub struct RateLimiter {
+    capacity: u32,
+    refill_per_sec: f64,
+    max_keys: usize,
+    key: KeyFn,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// A burst of `capacity` requests per key, refilled at `refill_per_sec` tokens per second.
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        assert!(capacity > 0 && refill_per_sec > 0.0, "rate limit capacity and refill rate must be positive");
+        Self { capacity, refill_per_sec, max_keys: DEFAULT_MAX_KEYS, key: Box::new(client_key), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// `n` requests per minute per key, all of which may arrive at once.
+    pub fn per_minute(n: u32) -> Self {
+        Self::new(n, f64::from(n) / 60.0)
+    }
+
+    /// Key requests by `key` instead of client IP, e.g. a username or token. Requests for which
+    /// it returns `None` are not limited.
+    pub fn key_by(mut self, key: impl Fn(&Request) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// Keep at most `n` buckets in memory.
+    pub fn max_keys(mut self, n: usize) -> Self {
+        self.max_keys = n.max(1);
+        self
+    }
+
+    /// Take a token from `key`'s bucket if one is available.
+    pub fn check(&self, key: &str) -> Decision {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(key) && buckets.len() >= self.max_keys {
+            self.evict(&mut buckets, now);
+        }
+        let capacity = f64::from(self.capacity);
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let retry_after = if bucket.tokens >= 1.0 { Duration::ZERO } else { Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_sec) };
+        Decision { allowed, remaining: bucket.tokens as u32, retry_after, reset: Duration::from_secs_f64((capacity - bucket.tokens) / self.refill_per_sec) }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_sec).min(f64::from(self.capacity))
+    }
+
+    /// Make room for one more bucket.
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        // A full bucket behaves exactly like a missing one.
+        let capacity = f64::from(self.capacity);
+        buckets.retain(|_, b| self.refilled(b, now) < capacity);
+        if buckets.len() >= self.max_keys
+            && let Some(oldest) = buckets.iter().min_by_key(|(_, b)| b.updated).map(|(k, _)| k.clone())
+        {
+            buckets.remove(&oldest);
+        }
+    }
+
+    /// Number of buckets currently held.
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        let Some(key) = (self.key)(req) else {
+            return next.run(req, stream);
+        };
+        let decision = self.check(&key);
+        if decision.allowed {
+            return next.run(req, stream);
+        }
+
+        let ceil_secs = |d: Duration| d.as_secs() + u64::from(d.subsec_nanos() > 0);
+        let policy = format!("{};w={}", self.capacity, ceil_secs(Duration::from_secs_f64(f64::from(self.capacity) / self.refill_per_sec)));
+        let error = HttpError::new(429, "too many requests")
+            .header("Retry-After", &ceil_secs(decision.retry_after).max(1).to_string())
+            .header("RateLimit-Limit", &self.capacity.to_string())
+            .header("RateLimit-Remaining", "0")
+            .header("RateLimit-Reset", &ceil_secs(decision.reset).to_string())
+            .header("RateLimit-Policy", &policy);
+        req.send_error(stream, error)
+    }
+}
+
+/// Resolved client IP, falling back to the peer address.
+fn client_key(req: &Request) -> Option<String> {
+    req.client_ip.or(req.connection.peer_addr.map(|a| a.ip())).map(|ip| ip.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router, write_response};
+
+    #[test]
+    fn rate_limiter_rejects_with_headers_per_key() {
+        let mut router = Router::new();
+        router.add_route(Method::Post, "/signin", |_req, stream| write_response(stream, 200, "OK", "text/plain", b"ok")).wrap(
+            RateLimiter::new(2, 1.0 / 30.0).key_by(|req| req.header("x-user").map(str::to_string)),
+        );
+        let signin = |user: &str| {
+            let mut req = Request::new("POST", "/signin");
+            req.headers.insert("x-user".to_string(), user.to_string());
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert!(signin("alice").starts_with("HTTP/1.1 200"));
+        assert!(signin("alice").starts_with("HTTP/1.1 200"));
+        let rejected = signin("alice");
+        assert!(rejected.starts_with("HTTP/1.1 429 Too Many Requests"));
+        assert!(rejected.contains("Retry-After: 30\r\n"));
+        assert!(rejected.contains("RateLimit-Limit: 2\r\n"));
+        assert!(rejected.contains("RateLimit-Remaining: 0\r\n"));
+        assert!(rejected.contains("RateLimit-Policy: 2;w=60\r\n"));
+        assert!(signin("bob").starts_with("HTTP/1.1 200"));
+
+        // No key, no limit.
+        let mut out = Vec::new();
+        router.handle(Request::new("POST", "/signin"), &mut out).unwrap();
+        assert!(out.starts_with(b"HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn rate_limiter_bounds_key_count() {
+        let limiter = RateLimiter::per_minute(10).max_keys(2);
+        for key in ["a", "b", "c", "d"] {
+            assert!(limiter.check(key).allowed);
+        }
+        assert_eq!(limiter.len(), 2);
+        // "a" was evicted, so it starts over with a full bucket.
+        assert_eq!(limiter.check("a").remaining, 9);
+    }
+}
@@ -0,0 +1,525 @@
+//! Recording requests and their responses to a JSON-lines file, and replaying a recording
+//! against a [`Router`] or a running server to find responses that changed.
+//!
+//! Each line of a recording is one exchange:
+//!
+//! ```json
+//! {"time":"2026-03-01T12:00:00.000Z","request_id":"…","request":{"method":"POST","path":"/api/notes","version":"HTTP/1.1","headers":[["content-type","application/json"]],"body":"{}"},"response":{"status":201,"headers":[["content-type","application/json"]],"body":"{…}"}}
+//! ```
+//!
+//! Bodies that are not UTF-8 are stored as `body_base64` instead of `body`. The
+//! `examples/replay.rs` tool replays a recording against a server from the command line.
+
+use std::fmt;
+use std::io::{self, BufRead, Cursor, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::client;
+use crate::date;
+use crate::json::{self, Value};
+use crate::middleware::{Middleware, Next};
+use crate::request::{Request, RequestParser};
+use crate::response::Tee;
+use crate::router::Router;
+
+/// Headers replaced by `[redacted]` in recordings unless [`Recorder::redact`] says otherwise.
+const DEFAULT_REDACTED: [&str; 4] = ["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+/// Headers [`Replay`] does not compare by default, since they differ on every response.
+const DEFAULT_IGNORED: [&str; 2] = ["date", "x-request-id"];
+
+/// Middleware appending every request and its response to a recording, one JSON line each.
+///
+/// Wrap it around the whole router to record responses as clients saw them. Credentials are
+/// redacted by default, so a replay of requests that needed them will differ. Streaming routes
+/// read their bodies after middleware runs, so their request bodies are recorded empty.
+pub struct Recorder {
+    out: Mutex<Box<dyn Write + Send>>,
+    redact: Vec<String>,
+    redact_body: Vec<String>,
+    max_body: usize,
+}
+
+impl Recorder {
+    /// Record to `out`, e.g. a [`RotatingFile`](crate::RotatingFile).
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self { out: Mutex::new(Box::new(out)), redact: DEFAULT_REDACTED.iter().map(|h| h.to_string()).collect(), redact_body: Vec::new(), max_body: 1024 * 1024 }
+    }
+
+    /// Headers whose values are replaced by `[redacted]`, in requests and responses. Defaults to
+    /// `Authorization`, `Proxy-Authorization`, `Cookie` and `Set-Cookie`; pass `&[]` to record
+    /// everything, e.g. on a developer machine.
+    pub fn redact(mut self, headers: &[&str]) -> Self {
+        self.redact = headers.iter().map(|h| h.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Members of JSON request and response bodies whose values are replaced by `"[redacted]"`,
+    /// at any depth, e.g. `&["password", "session_token"]`. Bodies that are not JSON are
+    /// recorded as they are.
+    pub fn redact_body(mut self, fields: &[&str]) -> Self {
+        self.redact_body = fields.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// Largest response recorded in full, in bytes (1 MiB by default). Only the status of larger
+    /// responses is kept, marked `"truncated":true`.
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    fn headers(&self, headers: &[(String, String)]) -> Vec<(String, String)> {
+        headers.iter().map(|(name, value)| (name.clone(), if self.redact.contains(name) { "[redacted]".to_string() } else { value.clone() })).collect()
+    }
+
+    fn body(&self, body: &[u8]) -> Vec<u8> {
+        if self.redact_body.is_empty() {
+            return body.to_vec();
+        }
+        let Some(mut value) = std::str::from_utf8(body).ok().and_then(|text| json::parse(text).ok()) else {
+            return body.to_vec();
+        };
+        // Re-serialized only when something was redacted, so other bodies keep their formatting.
+        if redact_members(&mut value, &self.redact_body) { value.to_string().into_bytes() } else { body.to_vec() }
+    }
+}
+
+/// Replace the values of members named in `fields` throughout `value`; whether any were.
+fn redact_members(value: &mut Value, fields: &[String]) -> bool {
+    let mut redacted = false;
+    match value {
+        Value::Object(members) => {
+            for (name, value) in members {
+                if fields.contains(name) {
+                    *value = Value::String("[redacted]".to_string());
+                    redacted = true;
+                } else {
+                    redacted |= redact_members(value, fields);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redacted |= redact_members(item, fields);
+            }
+        }
+        _ => {}
+    }
+    redacted
+}
+
+impl Middleware for Recorder {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        let mut tee = Tee::new(stream, self.max_body.saturating_add(64 * 1024));
+        let result = next.run(req, &mut tee);
+
+        let response = if tee.overflowed { None } else { client::read_response(&mut Cursor::new(&tee.captured), &mut RequestParser::new(), &req.method, u64::MAX).ok().flatten() };
+        let response = match response {
+            Some((resp, _)) => RecordedResponse { status: resp.status, headers: self.headers(&sorted(resp.headers)), body: self.body(&resp.body), truncated: false },
+            // Too large, or not a response we can parse: keep what the status line says.
+            None => {
+                let status = tee.captured.get(9..12).and_then(|s| std::str::from_utf8(s).ok()).and_then(|s| s.parse().ok()).unwrap_or(0);
+                RecordedResponse { status, headers: Vec::new(), body: Vec::new(), truncated: true }
+            }
+        };
+        let exchange = Exchange {
+            time: date::format_rfc3339(req.received_at),
+            request_id: req.request_id.clone(),
+            request: RecordedRequest { method: req.method.clone(), path: req.path.clone(), version: req.version.clone(), headers: self.headers(&sorted(req.headers.clone())), body: self.body(&req.body) },
+            response,
+        };
+        let mut line = exchange.to_json();
+        line.push('\n');
+        if let Err(e) = self.out.lock().unwrap().write_all(line.as_bytes()) {
+            eprintln!("failed to write request recording: {}", e);
+        }
+        result
+    }
+}
+
+fn sorted(headers: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = headers.into_iter().collect();
+    headers.sort();
+    headers
+}
+
+/// One recorded request and its response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exchange {
+    /// When the request arrived, in RFC 3339 format.
+    pub time: String,
+    pub request_id: String,
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query string.
+    pub path: String,
+    pub version: String,
+    /// Lower-cased names, sorted.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u16,
+    /// Lower-cased names, sorted.
+    pub headers: Vec<(String, String)>,
+    /// The body with any chunked framing removed.
+    pub body: Vec<u8>,
+    /// Only the status was recorded; see [`Recorder::max_body`].
+    pub truncated: bool,
+}
+
+impl Exchange {
+    /// Read every exchange in a recording.
+    pub fn read_all(recording: impl BufRead) -> io::Result<Vec<Exchange>> {
+        let mut exchanges = Vec::new();
+        for (n, line) in recording.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange = Exchange::from_json(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e)))?;
+            exchanges.push(exchange);
+        }
+        Ok(exchanges)
+    }
+
+    /// Parse one line of a recording.
+    pub fn from_json(line: &str) -> io::Result<Exchange> {
+        let value = json::parse(line)?;
+        let missing = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("missing or invalid `{}`", what));
+        let string = |value: &Value, name: &str| value.get(name).and_then(Value::as_str).map(str::to_string).ok_or_else(|| missing(name));
+        let headers = |value: &Value| -> io::Result<Vec<(String, String)>> {
+            let pairs = value.get("headers").and_then(Value::as_array).ok_or_else(|| missing("headers"))?;
+            pairs
+                .iter()
+                .map(|pair| match pair.as_array() {
+                    Some([Value::String(name), Value::String(value)]) => Ok((name.clone(), value.clone())),
+                    _ => Err(missing("headers")),
+                })
+                .collect()
+        };
+        let body = |value: &Value| -> io::Result<Vec<u8>> {
+            match (value.get("body").and_then(Value::as_str), value.get("body_base64").and_then(Value::as_str)) {
+                (Some(text), _) => Ok(text.as_bytes().to_vec()),
+                (None, Some(encoded)) => base64_decode(encoded).ok_or_else(|| missing("body_base64")),
+                (None, None) => Ok(Vec::new()),
+            }
+        };
+
+        let request = value.get("request").ok_or_else(|| missing("request"))?;
+        let response = value.get("response").ok_or_else(|| missing("response"))?;
+        let status = response.get("status").and_then(Value::as_f64).filter(|s| (100.0..1000.0).contains(s)).ok_or_else(|| missing("status"))?;
+        Ok(Exchange {
+            time: string(&value, "time")?,
+            request_id: string(&value, "request_id")?,
+            request: RecordedRequest { method: string(request, "method")?, path: string(request, "path")?, version: string(request, "version")?, headers: headers(request)?, body: body(request)? },
+            response: RecordedResponse {
+                status: status as u16,
+                headers: headers(response)?,
+                body: body(response)?,
+                truncated: response.get("truncated").and_then(Value::as_bool).unwrap_or(false),
+            },
+        })
+    }
+
+    /// This exchange as one line of a recording, without the newline.
+    pub fn to_json(&self) -> String {
+        let headers = |headers: &[(String, String)]| headers.iter().map(|(name, value)| format!("[{},{}]", json::string(name), json::string(value))).collect::<Vec<_>>().join(",");
+        let body = |body: &[u8]| match std::str::from_utf8(body) {
+            Ok(text) => format!("\"body\":{}", json::string(text)),
+            Err(_) => format!("\"body_base64\":\"{}\"", base64_encode(body)),
+        };
+        let (req, resp) = (&self.request, &self.response);
+        let truncated = if resp.truncated { ",\"truncated\":true" } else { "" };
+        format!(
+            "{{\"time\":{},\"request_id\":{},\"request\":{{\"method\":{},\"path\":{},\"version\":{},\"headers\":[{}],{}}},\"response\":{{\"status\":{},\"headers\":[{}],{}{}}}}}",
+            json::string(&self.time),
+            json::string(&self.request_id),
+            json::string(&req.method),
+            json::string(&req.path),
+            json::string(&req.version),
+            headers(&req.headers),
+            body(&req.body),
+            resp.status,
+            headers(&resp.headers),
+            body(&resp.body),
+            truncated
+        )
+    }
+}
+
+/// A way the replayed response differs from the recorded one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// `status`, `header <name>` or `body`.
+    pub what: String,
+    pub recorded: String,
+    pub replayed: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: recorded {}, replayed {}", self.what, self.recorded, self.replayed)
+    }
+}
+
+/// Replays recorded requests and compares the responses with the recorded ones.
+///
+/// ```rust,no_run
+/// use rusthttp::record::{Exchange, Replay};
+///
+/// let recording = std::io::BufReader::new(std::fs::File::open("requests.jsonl")?);
+/// let replay = Replay::new().ignore_header("etag");
+/// for exchange in Exchange::read_all(recording)? {
+///     for difference in replay.against_server("127.0.0.1:8080", &exchange)? {
+///         println!("{} {}: {}", exchange.request.method, exchange.request.path, difference);
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Replay {
+    ignored: Vec<String>,
+    timeout: Duration,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replay {
+    /// Compares status, headers other than `Date` and `X-Request-Id`, and body.
+    pub fn new() -> Self {
+        Self { ignored: DEFAULT_IGNORED.iter().map(|h| h.to_string()).collect(), timeout: Duration::from_secs(30) }
+    }
+
+    /// Do not compare header `name`, e.g. `ETag` for resources that embed timestamps.
+    pub fn ignore_header(mut self, name: &str) -> Self {
+        self.ignored.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// How long to wait for a server's response (30 seconds by default).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Handle the recorded request with `router` and compare.
+    pub fn against_router(&self, router: &Router, exchange: &Exchange) -> io::Result<Vec<Difference>> {
+        let recorded = &exchange.request;
+        let mut req = Request::new(&recorded.method, &recorded.path);
+        req.version = recorded.version.clone();
+        req.headers = recorded.headers.iter().cloned().collect();
+        req.body = recorded.body.clone();
+        req.assign_request_id();
+        let mut out = Vec::new();
+        router.handle(req, &mut out)?;
+        self.compare(exchange, &mut Cursor::new(out))
+    }
+
+    /// Send the recorded request to the server at `addr` (`host:port`) on a new connection and
+    /// compare. The request is sent as recorded, with `Connection: close`.
+    pub fn against_server(&self, addr: &str, exchange: &Exchange) -> io::Result<Vec<Difference>> {
+        let recorded = &exchange.request;
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut head = format!("{} {} {}\r\n", recorded.method, recorded.path, recorded.version);
+        for (name, value) in &recorded.headers {
+            if !matches!(name.as_str(), "content-length" | "transfer-encoding" | "connection") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", recorded.body.len()));
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&recorded.body)?;
+        self.compare(exchange, &mut stream)
+    }
+
+    fn compare(&self, exchange: &Exchange, response: &mut impl io::Read) -> io::Result<Vec<Difference>> {
+        let (replayed, _) = client::read_response(response, &mut RequestParser::new(), &exchange.request.method, u64::MAX)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no response"))?;
+        let recorded = &exchange.response;
+        let mut differences = Vec::new();
+        if replayed.status != recorded.status {
+            differences.push(Difference { what: "status".to_string(), recorded: recorded.status.to_string(), replayed: replayed.status.to_string() });
+        }
+        if recorded.truncated {
+            return Ok(differences);
+        }
+
+        let mut names: Vec<&str> = recorded.headers.iter().map(|(name, _)| name.as_str()).chain(replayed.headers.keys().map(String::as_str)).collect();
+        names.sort();
+        names.dedup();
+        for name in names.into_iter().filter(|name| !self.ignored.iter().any(|ignored| ignored == name)) {
+            let before = recorded.headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str());
+            let after = replayed.header(name);
+            // Redacted values cannot be compared.
+            if before != after && before != Some("[redacted]") {
+                let show = |value: Option<&str>| value.map_or_else(|| "nothing".to_string(), |value| format!("{:?}", value));
+                differences.push(Difference { what: format!("header {}", name), recorded: show(before), replayed: show(after) });
+            }
+        }
+        if replayed.body != recorded.body {
+            differences.push(Difference { what: "body".to_string(), recorded: preview(&recorded.body), replayed: preview(&replayed.body) });
+        }
+        Ok(differences)
+    }
+}
+
+/// A body shortened for display.
+fn preview(body: &[u8]) -> String {
+    const MAX: usize = 200;
+    let text = String::from_utf8_lossy(body);
+    match text.char_indices().nth(MAX) {
+        Some((cut, _)) => format!("{:?}… ({} bytes)", &text[..cut], body.len()),
+        None => format!("{:?}", text),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut n) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        bits = bits << 6 | value;
+        n += 6;
+        if n >= 8 {
+            n -= 8;
+            out.push((bits >> n) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Request, Router, write_response};
+
+    /// A recording destination the test can read back.
+    #[derive(Clone, Default)]
+    struct Shared(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn recorded_exchanges_replay_against_a_router() {
+        let app = |greeting: &'static str| {
+            let mut router = Router::new();
+            router.add_route(Method::Post, "/echo", |req, stream| write_response(stream, 200, "OK", "application/octet-stream", &req.body));
+            router.add_route(Method::Get, "/hello", move |_req, stream| write_response(stream, 200, "OK", "text/plain", greeting.as_bytes()));
+            router
+        };
+        let recording = Shared::default();
+        let mut router = app("hello");
+        router.wrap(Recorder::new(recording.clone()));
+
+        let mut req = Request::new("POST", "/echo?x=1");
+        req.headers.insert("authorization".to_string(), "Bearer secret".to_string());
+        req.body = vec![0xff, 0x00, b'a'];
+        router.handle(req, &mut Vec::new()).unwrap();
+        router.handle(Request::new("GET", "/hello"), &mut Vec::new()).unwrap();
+
+        let text = String::from_utf8(recording.0.lock().unwrap().clone()).unwrap();
+        assert!(text.contains(r#""headers":[["authorization","[redacted]"]],"body_base64":"/wBh"}"#), "{}", text);
+        let exchanges = Exchange::read_all(text.as_bytes()).unwrap();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(exchanges[0].request.path, "/echo?x=1");
+        assert_eq!(exchanges[0].response.body, vec![0xff, 0x00, b'a']);
+        assert_eq!(exchanges[1].to_json(), text.lines().nth(1).unwrap());
+
+        let replay = Replay::new();
+        assert_eq!(replay.against_router(&app("hello"), &exchanges[0]).unwrap(), Vec::new());
+        assert_eq!(replay.against_router(&app("hello"), &exchanges[1]).unwrap(), Vec::new());
+        let changed = replay.against_router(&app("hi"), &exchanges[1]).unwrap();
+        assert_eq!(changed.iter().map(ToString::to_string).collect::<Vec<_>>(), ["header content-length: recorded \"5\", replayed \"2\"", "body: recorded \"hello\", replayed \"hi\""]);
+        let mut gone = Router::new();
+        gone.add_route(Method::Get, "/other", |_req, stream| write_response(stream, 200, "OK", "text/plain", b""));
+        assert_eq!(replay.against_router(&gone, &exchanges[1]).unwrap()[0].to_string(), "status: recorded 200, replayed 404");
+    }
+
+    #[test]
+    fn close_delimited_responses_are_recorded_and_replayed() {
+        let app = || {
+            let mut router = Router::new();
+            // Like a proxied upstream that ends its body by closing the connection.
+            router.add_route(Method::Get, "/stream", |_req, stream| stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nuntil close"));
+            router
+        };
+        let recording = Shared::default();
+        let mut router = app();
+        router.wrap(Recorder::new(recording.clone()));
+        router.handle(Request::new("GET", "/stream"), &mut Vec::new()).unwrap();
+
+        let text = String::from_utf8(recording.0.lock().unwrap().clone()).unwrap();
+        let exchanges = Exchange::read_all(text.as_bytes()).unwrap();
+        assert_eq!(exchanges[0].response.body, b"until close");
+        assert_eq!(Replay::new().against_router(&app(), &exchanges[0]).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn json_body_fields_are_redacted() {
+        let mut router = Router::new();
+        router.add_route(Method::Post, "/login", |_req, stream| write_response(stream, 200, "OK", "application/json", br#"{"session_token": "abc", "user": {"name": "ann"}}"#));
+        router.add_route(Method::Post, "/echo", |req, stream| write_response(stream, 200, "OK", "application/json", &req.body));
+        let recording = Shared::default();
+        router.wrap(Recorder::new(recording.clone()).redact_body(&["password", "session_token"]));
+
+        let mut req = Request::new("POST", "/login");
+        req.body = br#"{"name": "ann", "password": "hunter2", "retry": [{"password": {"old": 1.5}}]}"#.to_vec();
+        router.handle(req, &mut Vec::new()).unwrap();
+        let mut req = Request::new("POST", "/echo");
+        req.body = br#"{"text": "password"}  "#.to_vec();
+        router.handle(req, &mut Vec::new()).unwrap();
+        let mut req = Request::new("POST", "/echo");
+        req.body = b"password=hunter2".to_vec();
+        router.handle(req, &mut Vec::new()).unwrap();
+
+        let text = String::from_utf8(recording.0.lock().unwrap().clone()).unwrap();
+        let exchanges = Exchange::read_all(text.as_bytes()).unwrap();
+        assert_eq!(exchanges[0].request.body, br#"{"name":"ann","password":"[redacted]","retry":[{"password":"[redacted]"}]}"#);
+        assert_eq!(exchanges[0].response.body, br#"{"session_token":"[redacted]","user":{"name":"ann"}}"#);
+        // Bodies without a redacted member, or that are not JSON, are kept as they were.
+        assert_eq!(exchanges[1].request.body, br#"{"text": "password"}  "#);
+        assert_eq!(exchanges[2].request.body, b"password=hunter2");
+    }
+}
@@ -1,97 +1,582 @@
 use std::collections::HashMap;
-use std::io::{self, Read};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::sync::{Arc, LazyLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Debug)]
+use crate::body::{Body, Framing};
+use crate::error::{HttpError, SharedErrorHandler, write_error};
+use crate::router::RouteNames;
+
+/// Largest request head (request line plus headers) the parser will buffer.
+pub const MAX_HEAD_BYTES: usize = 1024 * 1024;
+/// Largest body `parse_http_request` will buffer; also the default per-route body limit.
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+const READ_CHUNK: usize = 4096;
+
+/// Longest client-supplied `X-Request-Id` that is kept; longer ones are replaced.
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+
+static REQUEST_ID_PREFIX: LazyLock<u64> = LazyLock::new(|| RandomState::new().hash_one(SystemTime::now()));
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A new request id, unique within the process and unlikely to repeat across restarts.
+pub fn generate_request_id() -> String {
+    format!("{:012x}{:012x}", *REQUEST_ID_PREFIX & 0xffff_ffff_ffff, NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Whether a client-supplied request id is safe to log and echo: printable ASCII without spaces.
+fn acceptable_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// The connection a request arrived on.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionInfo {
+    /// Process-unique connection number, starting at 1. Zero for requests built by hand.
+    pub id: u64,
+    /// Remote end of the socket; `None` for requests not read from a network socket.
+    pub peer_addr: Option<SocketAddr>,
+    /// Local end of the socket.
+    pub local_addr: Option<SocketAddr>,
+    /// Negotiated TLS parameters; `None` for plain-text connections.
+    pub tls: Option<TlsInfo>,
+    /// Path of the Unix domain socket the connection arrived on; `None` for TCP.
+    pub unix_socket: Option<std::path::PathBuf>,
+}
+
+impl ConnectionInfo {
+    /// Whether the connection is encrypted.
+    pub fn is_secure(&self) -> bool {
+        self.tls.is_some()
+    }
+}
+
+/// Parameters negotiated during a TLS handshake.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// Protocol version, e.g. "TLSv1.3".
+    pub version: String,
+    /// Cipher suite name, e.g. "TLS13_AES_256_GCM_SHA384".
+    pub cipher_suite: Option<String>,
+    /// Application protocol chosen via ALPN, e.g. "http/1.1".
+    pub alpn_protocol: Option<String>,
+    /// Host name the client asked for via SNI.
+    pub server_name: Option<String>,
+}
+
+#[derive(Clone, Debug)]
 pub struct Request {
     /// HTTP method string, e.g. "GET" or "POST".
     pub method: String,
     /// Request path, including leading `/` and any query string.
     pub path: String,
+    /// Protocol version from the request line, e.g. "HTTP/1.1".
+    pub version: String,
     /// Lower-cased header names mapped to their values.
-    #[allow(dead_code)]
     pub headers: HashMap<String, String>,
     /// Raw body bytes.
     pub body: Vec<u8>,
+    /// The connection this request arrived on.
+    pub connection: ConnectionInfo,
+    /// Position of this request on its connection, starting at 1. The server closes each
+    /// connection after one response, so this is always 1 until it supports keep-alive.
+    pub sequence: u64,
+    /// When the request head finished arriving.
+    pub received_at: SystemTime,
+    /// Originating client address: the peer address, or the address reported by a trusted
+    /// proxy (see [`TrustedProxies`](crate::TrustedProxies)).
+    pub client_ip: Option<IpAddr>,
+    /// Correlation id, echoed in the `X-Request-Id` response header and in access logs. Empty
+    /// until assigned by the server or [`assign_request_id`](Self::assign_request_id).
+    pub request_id: String,
+    /// Values captured by the matched route's `:param` and `*wildcard` segments, in order,
+    /// percent-decoded.
+    pub params: Vec<(String, String)>,
+    /// Response media type chosen from the route's [`produces`](crate::Route::produces) list by
+    /// the `Accept` header; `None` if the route declares none.
+    pub response_type: Option<String>,
+    /// When the route's [deadline](crate::Route::deadline) passes, if it has one.
+    pub(crate) deadline: Option<Instant>,
+    /// Renders [`send_error`](Self::send_error) responses; set by the router.
+    pub(crate) error_handler: SharedErrorHandler,
+    /// Named routes of the router that dispatched this request, for [`url_for`](Self::url_for).
+    pub(crate) route_names: Arc<RouteNames>,
 }
 
-/// Parses an HTTP request from the given reader.
-///
-/// The parser reads until it finds `\r\n\r\n` and then reads the remainder of the
-/// body according to `Content-Length` if present.
-pub fn parse_http_request<R: Read>(stream: &mut R) -> io::Result<Request> {
-    fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-        haystack.windows(needle.len()).position(|w| w == needle)
-    }
-    
-    // Read until we have headers, then read body based on Content-Length (if any).
-    let mut buf = Vec::<u8>::new();
-    let mut tmp = [0u8; 4096];
-
-    // 1) Read until header terminator
-    loop {
-        let n = stream.read(&mut tmp)?;
-        if n == 0 {
-            // connection closed
-            break;
-        }
-        buf.extend_from_slice(&tmp[..n]);
-        if find_subsequence(&buf, b"\r\n\r\n").is_some() {
-            break;
-        }
-        // avoid runaway in this toy server
-        if buf.len() > 1024 * 1024 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "headers too large"));
+impl Request {
+    /// Create an HTTP/1.1 request with no headers and an empty body.
+    pub fn new(method: &str, path: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            connection: ConnectionInfo::default(),
+            sequence: 1,
+            received_at: SystemTime::now(),
+            client_ip: None,
+            request_id: String::new(),
+            params: Vec::new(),
+            response_type: None,
+            deadline: None,
+            error_handler: SharedErrorHandler::default(),
+            route_names: Arc::default(),
+        }
+    }
+
+    /// Value captured by the route parameter `name`, e.g. `id` for `/notes/:id`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Write `error` as rendered by the router's [`ErrorHandler`](crate::ErrorHandler), so
+    /// handler errors look like the built-in ones.
+    pub fn send_error(&self, stream: &mut dyn std::io::Write, error: HttpError) -> io::Result<()> {
+        write_error(stream, &*self.error_handler.0, Some(self), &error)
+    }
+
+    /// Time left before the route's [deadline](crate::Route::deadline), zero once it has passed;
+    /// `None` if the route has no deadline. Long-running handlers can check this between steps
+    /// and stop early, since their response can no longer be delivered after the deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// The entry of `offered` this request's `Accept` header prefers; see
+    /// [`negotiation::preferred_type`](crate::negotiation::preferred_type).
+    pub fn preferred_type<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        crate::negotiation::preferred_type(self.header("accept"), offered)
+    }
+
+    /// Build the URL of a named route of the router handling this request; see
+    /// [`Router::url_for`](crate::Router::url_for).
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> io::Result<String> {
+        self.route_names.url_for(name, params)
+    }
+
+    /// Take the id from the `X-Request-Id` header if it is acceptable, otherwise generate one.
+    pub fn assign_request_id(&mut self) {
+        self.request_id = match self.header("x-request-id") {
+            Some(id) if acceptable_request_id(id) => id.to_string(),
+            _ => generate_request_id(),
+        };
+    }
+
+    /// Look up a header by name, ignoring ASCII case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        match self.headers.get(name) {
+            Some(v) => Some(v.as_str()),
+            None => self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str()),
         }
     }
 
-    // If we never found headers end, treat as bad request
-    let header_end = find_subsequence(&buf, b"\r\n\r\n").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP request"))? + 4;
+    /// Value of the cookie `name` from the `Cookie` header, with surrounding quotes removed.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        let cookies = self.header("cookie")?;
+        cookies.split(';').filter_map(|pair| pair.trim().split_once('=')).find(|(n, _)| *n == name).map(|(_, v)| v.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(v))
+    }
+}
+
+/// A parsed request head borrowing from a [`RequestParser`]'s buffer.
+///
+/// Nothing is copied out of the buffer until [`RawRequest::to_request`] is called.
+pub struct RawRequest<'b> {
+    buf: &'b [u8],
+    method: Range<usize>,
+    target: Range<usize>,
+    version: Range<usize>,
+    headers: &'b [(Range<usize>, Range<usize>)],
+}
+
+impl<'b> RawRequest<'b> {
+    pub fn method(&self) -> &'b str {
+        self.text(&self.method)
+    }
+
+    /// Request target, including any query string.
+    pub fn path(&self) -> &'b str {
+        self.text(&self.target)
+    }
+
+    pub fn version(&self) -> &'b str {
+        self.text(&self.version)
+    }
+
+    /// Iterate over headers in the order they were received, names as sent.
+    pub fn headers(&self) -> impl Iterator<Item = (&'b str, &'b [u8])> + '_ {
+        self.headers.iter().map(|(name, value)| (self.text(name), &self.buf[value.clone()]))
+    }
 
-    let header_bytes = &buf[..header_end];
-    let header_text = String::from_utf8_lossy(header_bytes);
-    let mut lines = header_text.split("\r\n");
+    /// First header named `name` (ASCII case-insensitive), as raw bytes.
+    pub fn header_bytes(&self, name: &str) -> Option<&'b [u8]> {
+        self.headers().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+    }
 
-    let request_line = lines.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing request line"))?;
+    /// First header named `name`, if its value is valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&'b str> {
+        self.header_bytes(name).and_then(|v| std::str::from_utf8(v).ok())
+    }
 
-    let mut parts = request_line.split_whitespace();
-    let method = parts.next().unwrap_or("").to_string();
-    let path = parts.next().unwrap_or("").to_string();
+    /// The whole first line, without its CRLF. For a response head this is the status line.
+    pub(crate) fn start_line(&self) -> &'b str {
+        let end = find_crlf(self.buf, 0).unwrap_or(0);
+        std::str::from_utf8(&self.buf[..end]).unwrap_or("")
+    }
 
-    if method.is_empty() || path.is_empty() {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request line"));
+    /// Declared `Content-Length`, or `None` if absent or malformed.
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("content-length").and_then(|v| v.parse::<usize>().ok())
     }
 
-    let mut headers = HashMap::<String, String>::new();
-    for line in lines {
-        if line.is_empty() {
-            break;
+    /// Copy the head into an owned [`Request`] with the given body.
+    ///
+    /// Repeated headers are joined with `", "`; values that are not UTF-8 are converted lossily.
+    pub fn to_request(&self, body: Vec<u8>) -> Request {
+        let mut headers = HashMap::<String, String>::with_capacity(self.headers.len());
+        for (name, value) in self.headers() {
+            let value = String::from_utf8_lossy(value);
+            headers
+                .entry(name.to_ascii_lowercase())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
         }
-        if let Some((k, v)) = line.split_once(':') {
-            headers.insert(k.trim().to_ascii_lowercase(), v.trim().to_string());
+        Request {
+            method: self.method().to_string(),
+            path: self.path().to_string(),
+            version: self.version().to_string(),
+            headers,
+            body,
+            connection: ConnectionInfo::default(),
+            sequence: 1,
+            received_at: SystemTime::now(),
+            client_ip: None,
+            request_id: String::new(),
+            params: Vec::new(),
+            response_type: None,
+            deadline: None,
+            error_handler: SharedErrorHandler::default(),
+            route_names: Arc::default(),
         }
     }
 
-    let content_length = headers.get("content-length").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+    fn text(&self, range: &Range<usize>) -> &'b str {
+        // Ranges only ever cover bytes validated as ASCII during parsing.
+        std::str::from_utf8(&self.buf[range.clone()]).unwrap_or("")
+    }
+}
+
+/// Incremental HTTP/1.x request parser over a reusable buffer.
+///
+/// Bytes are appended with [`feed`](Self::feed) or read directly from a stream with
+/// [`read_head`](Self::read_head). The search for the end of the head resumes where the last
+/// one stopped, so a head delivered in many small reads is scanned only once. Headers are
+/// recorded as offsets into the buffer; after a request is handled, [`consume`](Self::consume)
+/// keeps the allocation for the next request on the same connection.
+pub struct RequestParser {
+    buf: Vec<u8>,
+    scanned: usize,
+    head_len: Option<usize>,
+    method: Range<usize>,
+    target: Range<usize>,
+    version: Range<usize>,
+    headers: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl Default for RequestParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        Self { buf: Vec::with_capacity(READ_CHUNK), scanned: 0, head_len: None, method: 0..0, target: 0..0, version: 0..0, headers: Vec::new() }
+    }
 
-    let mut body = Vec::<u8>::new();
+    /// Append raw bytes received from the peer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
 
-    // Anything already read after headers is body prefix
-    let already = buf.len().saturating_sub(header_end);
-    if already > 0 {
-        body.extend_from_slice(&buf[header_end..]);
+    /// Try to parse a complete head from the bytes fed so far.
+    ///
+    /// Returns `Ok(None)` if more input is needed.
+    pub fn parse(&mut self) -> io::Result<Option<RawRequest<'_>>> {
+        if self.advance()? { Ok(Some(self.raw())) } else { Ok(None) }
     }
 
-    // Read remaining body
-    while body.len() < content_length {
-        let n = stream.read(&mut tmp)?;
-        if n == 0 {
-            break;
+    /// Read from `stream` until a complete head is buffered.
+    ///
+    /// Returns `Ok(None)` if the stream ends before any byte of a new request arrives, which is
+    /// how an idle connection closes. Any bytes read past the head stay buffered as the start of
+    /// the body; see [`read_body`](Self::read_body).
+    pub fn read_head<R: Read + ?Sized>(&mut self, stream: &mut R) -> io::Result<Option<RawRequest<'_>>> {
+        while !self.advance()? {
+            if self.fill(stream)? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP request"));
+            }
         }
-        body.extend_from_slice(&tmp[..n]);
-        if body.len() > 1024 * 1024 {
+        Ok(Some(self.raw()))
+    }
+
+    /// Read one request, buffering a body of at most [`MAX_BODY_BYTES`].
+    ///
+    /// This is the only point where the head is copied into owned strings. Returns `Ok(None)` if
+    /// the stream ends before a new request starts.
+    pub fn read_request<R: Read + ?Sized>(&mut self, stream: &mut R) -> io::Result<Option<Request>> {
+        let mut req = match self.read_head(stream)? {
+            Some(raw) => raw.to_request(Vec::new()),
+            None => return Ok(None),
+        };
+        let framing = Framing::from_request(&req)?;
+        req.body = Body::new(&mut self.body_reader(stream), framing, MAX_BODY_BYTES as u64).read_to_vec()?;
+        self.consume();
+        Ok(Some(req))
+    }
+
+    /// A buffered reader over the bytes following the head: first those already read from
+    /// `stream` by the parser, then `stream` itself.
+    pub fn body_reader<'p, R: Read + ?Sized>(&'p mut self, stream: &'p mut R) -> impl BufRead + 'p {
+        BodyReader { parser: self, stream }
+    }
+
+    /// Bytes buffered after the parsed head.
+    pub fn buffered_body(&self) -> &[u8] {
+        let start = self.head_len.unwrap_or(0);
+        &self.buf[start..]
+    }
+
+    /// Read exactly `len` body bytes, using buffered bytes first.
+    ///
+    /// Stops early if the stream ends. Fails if `len` exceeds `limit`.
+    pub fn read_body<R: Read + ?Sized>(&mut self, stream: &mut R, len: usize, limit: usize) -> io::Result<Vec<u8>> {
+        if len > limit {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "body too large"));
         }
+        let head_len = self.head_len.unwrap_or(0);
+        let buffered = (self.buf.len() - head_len).min(len);
+        let mut body = Vec::with_capacity(len);
+        body.extend_from_slice(&self.buf[head_len..head_len + buffered]);
+        self.buf.drain(head_len..head_len + buffered);
+
+        body.resize(len, 0);
+        let mut filled = buffered;
+        while filled < len {
+            let n = stream.read(&mut body[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        body.truncate(filled);
+        Ok(body)
+    }
+
+    /// Discard the current head, keeping any bytes that follow it (a pipelined request).
+    pub fn consume(&mut self) {
+        if let Some(len) = self.head_len.take() {
+            self.buf.drain(..len);
+        }
+        self.scanned = 0;
+        self.headers.clear();
+    }
+
+    fn fill<R: Read + ?Sized>(&mut self, stream: &mut R) -> io::Result<usize> {
+        let len = self.buf.len();
+        self.buf.resize(len + READ_CHUNK, 0);
+        let n = match stream.read(&mut self.buf[len..]) {
+            Ok(n) => n,
+            Err(e) => {
+                self.buf.truncate(len);
+                return Err(e);
+            }
+        };
+        self.buf.truncate(len + n);
+        Ok(n)
+    }
+
+    /// Scan newly buffered bytes for the end of the head and parse it once found.
+    fn advance(&mut self) -> io::Result<bool> {
+        if self.head_len.is_some() {
+            return Ok(true);
+        }
+        // Resume three bytes back so a terminator split across reads is still found.
+        let from = self.scanned.saturating_sub(3);
+        let end = match self.buf[from..].windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(pos) => from + pos + 4,
+            None => {
+                self.scanned = self.buf.len();
+                if self.buf.len() > MAX_HEAD_BYTES {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "headers too large"));
+                }
+                return Ok(false);
+            }
+        };
+        if end > MAX_HEAD_BYTES {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "headers too large"));
+        }
+        self.parse_head(end)?;
+        self.head_len = Some(end);
+        Ok(true)
+    }
+
+    fn parse_head(&mut self, end: usize) -> io::Result<()> {
+        let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let head = &self.buf[..end - 2];
+
+        let line_end = find_crlf(head, 0).ok_or_else(|| bad("missing request line"))?;
+        let line = &head[..line_end];
+        if !line.is_ascii() {
+            return Err(bad("bad request line"));
+        }
+        let mut tokens = split_whitespace(line);
+        self.method = tokens.next().ok_or_else(|| bad("bad request line"))?;
+        self.target = tokens.next().ok_or_else(|| bad("bad request line"))?;
+        self.version = tokens.next().unwrap_or(line_end..line_end);
+
+        self.headers.clear();
+        let mut pos = line_end + 2;
+        while pos < head.len() {
+            let eol = find_crlf(head, pos).unwrap_or(head.len());
+            let line = &head[pos..eol];
+            if let Some(colon) = line.iter().position(|&b| b == b':') {
+                let name = trim(line, 0..colon);
+                if !self.buf[name.clone()].is_ascii() {
+                    return Err(bad("bad header name"));
+                }
+                let value = trim(line, colon + 1..line.len());
+                self.headers.push((pos + name.start..pos + name.end, pos + value.start..pos + value.end));
+            }
+            pos = eol + 2;
+        }
+        Ok(())
+    }
+
+    fn raw(&self) -> RawRequest<'_> {
+        RawRequest { buf: &self.buf, method: self.method.clone(), target: self.target.clone(), version: self.version.clone(), headers: &self.headers }
     }
+}
 
-    Ok(Request { method, path, headers, body })
+struct BodyReader<'p, R: ?Sized> {
+    parser: &'p mut RequestParser,
+    stream: &'p mut R,
+}
+
+impl<R: Read + ?Sized> Read for BodyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read + ?Sized> BufRead for BodyReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.parser.buffered_body().is_empty() {
+            self.parser.fill(self.stream)?;
+        }
+        Ok(self.parser.buffered_body())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let start = self.parser.head_len.unwrap_or(0);
+        self.parser.buf.drain(start..start + amt);
+    }
+}
+
+fn find_crlf(buf: &[u8], from: usize) -> Option<usize> {
+    buf[from..].windows(2).position(|w| w == b"\r\n").map(|p| from + p)
+}
+
+fn split_whitespace(line: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        while pos < line.len() && line[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos == line.len() {
+            return None;
+        }
+        let start = pos;
+        while pos < line.len() && !line[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        Some(start..pos)
+    })
+}
+
+fn trim(line: &[u8], mut range: Range<usize>) -> Range<usize> {
+    while range.start < range.end && line[range.start].is_ascii_whitespace() {
+        range.start += 1;
+    }
+    while range.end > range.start && line[range.end - 1].is_ascii_whitespace() {
+        range.end -= 1;
+    }
+    range
+}
+
+/// Parses an HTTP request from the given reader.
+///
+/// The parser reads until it finds `\r\n\r\n` and then reads the remainder of the
+/// body according to `Content-Length` or chunked `Transfer-Encoding` if present.
+pub fn parse_http_request<R: Read>(stream: &mut R) -> io::Result<Request> {
+    RequestParser::new().read_request(stream)?.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP request"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn parser_resumes_across_split_reads() {
+        let mut parser = RequestParser::new();
+        parser.feed(b"GET /a HTTP/1.1\r\nX-One: 1\r");
+        assert!(parser.parse().unwrap().is_none());
+        parser.feed(b"\n\r");
+        assert!(parser.parse().unwrap().is_none());
+        parser.feed(b"\nGET /b HTTP/1.1\r\n\r\n");
+        let raw = parser.parse().unwrap().expect("complete head");
+        assert_eq!(raw.method(), "GET");
+        assert_eq!(raw.path(), "/a");
+        assert_eq!(raw.version(), "HTTP/1.1");
+        assert_eq!(raw.header("x-one"), Some("1"));
+
+        parser.consume();
+        let raw = parser.parse().unwrap().expect("pipelined head");
+        assert_eq!(raw.path(), "/b");
+    }
+
+    #[test]
+    fn parser_joins_repeated_headers() {
+        let mut data = Cursor::new(b"GET / HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1\r\nx-forwarded-for: 10.0.0.2\r\n\r\n".to_vec());
+        let req = parse_http_request(&mut data).expect("parse");
+        assert_eq!(req.header("X-Forwarded-For"), Some("10.0.0.1, 10.0.0.2"));
+    }
+
+    #[test]
+    fn parser_keeps_bytes_after_body() {
+        let mut data = Cursor::new(b"POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\nokGET /b HTTP/1.1\r\n\r\n".to_vec());
+        let mut parser = RequestParser::new();
+        let first = parser.read_request(&mut data).unwrap().expect("first");
+        assert_eq!(first.body, b"ok");
+        let second = parser.read_request(&mut data).unwrap().expect("second");
+        assert_eq!(second.path, "/b");
+        assert!(parser.read_request(&mut data).unwrap().is_none());
+    }
 }
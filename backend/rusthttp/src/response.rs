@@ -5,24 +5,204 @@ use std::io::{self, Write};
 /// This function writes a minimal set of headers and the raw body. It is generic over any
 /// `Write` implementation to make testing and embedding easier.
 pub fn write_response<W: Write + ?Sized>(stream: &mut W, status_code: u16, status_text: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write_response_with_headers(stream, status_code, status_text, content_type, &[], body)
+}
+
+/// Like [`write_response`], with extra `(name, value)` headers after the standard ones.
+pub fn write_response_with_headers<W: Write + ?Sized>(stream: &mut W, status_code: u16, status_text: &str, content_type: &str, extra: &[(&str, &str)], body: &[u8]) -> io::Result<()> {
     // Minimal CORS headers for browser calls
-    let headers = format!(
+    let mut headers = format!(
         "HTTP/1.1 {} {}\r\n\
          Content-Type: {}\r\n\
          Content-Length: {}\r\n\
          Connection: close\r\n\
          Access-Control-Allow-Origin: http://localhost:3000\r\n\
          Access-Control-Allow-Credentials: true\r\n\
-         Access-Control-Allow-Headers: Content-Type, Authorization\r\n\
-         Access-Control-Allow-Methods: GET,POST,PATCH,DELETE,OPTIONS\r\n\
-         Access-Control-Max-Age: 86400\r\n\
-         \r\n",
+         Access-Control-Allow-Headers: Content-Type, Authorization, If-Match, If-None-Match\r\n\
+         Access-Control-Expose-Headers: ETag, Location, Link\r\n\
+         Access-Control-Allow-Methods: GET,POST,PUT,PATCH,DELETE,OPTIONS\r\n\
+         Access-Control-Max-Age: 86400\r\n",
         status_code,
         status_text,
         content_type,
         body.len()
     );
+    for (name, value) in extra {
+        headers.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    headers.push_str("\r\n");
     stream.write_all(headers.as_bytes())?;
     stream.write_all(body)?;
     Ok(())
 }
+
+const MAX_METERED_HEAD: usize = 64 * 1024;
+
+/// Passes a response through while noting its status code and body size.
+///
+/// Interim `1xx` responses are skipped, so `status` is that of the final response.
+pub(crate) struct ResponseMeter<W> {
+    inner: W,
+    head: Vec<u8>,
+    in_body: bool,
+    status: u16,
+    body_bytes: u64,
+}
+
+impl<W: Write> ResponseMeter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, head: Vec::new(), in_body: false, status: 0, body_bytes: 0 }
+    }
+
+    /// Status of the final response, or 0 if none has been written.
+    pub(crate) fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub(crate) fn body_bytes(&self) -> u64 {
+        self.body_bytes
+    }
+
+    /// Track `buf`, which the caller has just written.
+    fn observe(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if self.in_body {
+                self.body_bytes += buf.len() as u64;
+                return;
+            }
+            let start = self.head.len();
+            self.head.extend_from_slice(buf);
+            let end = match self.head.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(pos) => pos + 4,
+                // Not a response head we understand; count the rest as body.
+                None if self.head.len() > MAX_METERED_HEAD => self.head.len(),
+                None => return,
+            };
+            let status = self.head.get(9..12).and_then(|s| std::str::from_utf8(s).ok()).and_then(|s| s.parse::<u16>().ok()).unwrap_or(0);
+            if !(100..200).contains(&status) {
+                self.status = status;
+                self.in_body = true;
+            }
+            self.head.clear();
+            buf = &buf[end - start..];
+        }
+    }
+}
+
+impl<W: Write> Write for ResponseMeter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.observe(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Adds fixed headers to the final response head written through it; interim `1xx` heads pass
+/// through unchanged.
+///
+/// Heads are held back until complete, so handlers can write them in any number of pieces. A
+/// head that is still incomplete when the writer is flushed is passed on unchanged.
+pub(crate) struct InjectHeaders<W> {
+    inner: W,
+    headers: Vec<(String, String)>,
+    if_absent: bool,
+    head: Vec<u8>,
+    in_body: bool,
+}
+
+impl<W: Write> InjectHeaders<W> {
+    pub(crate) fn new(inner: W, headers: &[(&str, &str)]) -> Self {
+        let headers = headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        Self { inner, headers, if_absent: false, head: Vec::new(), in_body: false }
+    }
+
+    /// Leave out headers the response already has, so handlers can override them.
+    pub(crate) fn if_absent(mut self) -> Self {
+        self.if_absent = true;
+        self
+    }
+
+    fn added_headers(&self, head: &[u8]) -> String {
+        let head = String::from_utf8_lossy(head);
+        let present = |name: &str| head.split("\r\n").skip(1).any(|line| line.split_once(':').is_some_and(|(n, _)| n.trim().eq_ignore_ascii_case(name)));
+        self.headers.iter().filter(|(name, _)| !self.if_absent || !present(name)).map(|(name, value)| format!("{}: {}\r\n", name, value)).collect()
+    }
+}
+
+impl<W: Write> Write for InjectHeaders<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.in_body {
+            return self.inner.write(buf);
+        }
+        let start = self.head.len();
+        self.head.extend_from_slice(buf);
+        let Some(pos) = self.head.windows(4).position(|w| w == b"\r\n\r\n") else {
+            if self.head.len() > MAX_METERED_HEAD {
+                self.in_body = true;
+                self.inner.write_all(&std::mem::take(&mut self.head))?;
+            }
+            return Ok(buf.len());
+        };
+        let end = pos + 4;
+        let status = self.head.get(9..12).and_then(|s| std::str::from_utf8(s).ok()).and_then(|s| s.parse::<u16>().ok()).unwrap_or(0);
+        // Interim responses are followed by another head.
+        let interim = (100..200).contains(&status);
+        if interim {
+            self.inner.write_all(&self.head[..end])?;
+        } else {
+            self.inner.write_all(&self.head[..pos + 2])?;
+            self.inner.write_all(self.added_headers(&self.head[..pos]).as_bytes())?;
+            self.inner.write_all(b"\r\n")?;
+        }
+        self.head.clear();
+        self.in_body = !interim;
+        let rest = &buf[end - start..];
+        if !rest.is_empty() {
+            self.write_all(rest)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.head.is_empty() {
+            self.inner.write_all(&std::mem::take(&mut self.head))?;
+            self.in_body = true;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Passes writes through while keeping a copy of the first `limit` bytes.
+pub(crate) struct Tee<'a> {
+    inner: &'a mut dyn Write,
+    pub(crate) captured: Vec<u8>,
+    limit: usize,
+    /// More than `limit` bytes were written, so `captured` is incomplete.
+    pub(crate) overflowed: bool,
+}
+
+impl<'a> Tee<'a> {
+    pub(crate) fn new(inner: &'a mut dyn Write, limit: usize) -> Self {
+        Self { inner, captured: Vec::new(), limit, overflowed: false }
+    }
+}
+
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if !self.overflowed && self.captured.len() + n <= self.limit {
+            self.captured.extend_from_slice(&buf[..n]);
+        } else {
+            self.overflowed = true;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,21 +1,45 @@
-use std::io::{self, Write};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use crate::request::Request;
+use crate::body::{Body, Framing};
+use crate::error::{ErrorHandler, HttpError, SharedErrorHandler};
+use crate::middleware::{Middleware, Next};
+use crate::negotiation;
+use crate::openapi::OperationDoc;
+use crate::request::{MAX_BODY_BYTES, Request};
 use crate::response::write_response;
+use crate::tree::{self, Node};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Method {
     Get,
     Post,
+    Put,
     Patch,
     Delete,
 }
 
 impl Method {
+    /// Every supported method, e.g. for registering a handler that accepts any of them.
+    pub const ALL: [Method; 5] = [Method::Get, Method::Post, Method::Put, Method::Patch, Method::Delete];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Patch => "PATCH",
+            Self::Delete => "DELETE",
+        }
+    }
+
     fn from_str(method: &str) -> Option<Self> {
         match method {
             "GET" => Some(Self::Get),
             "POST" => Some(Self::Post),
+            "PUT" => Some(Self::Put),
             "PATCH" => Some(Self::Patch),
             "DELETE" => Some(Self::Delete),
             _ => None,
@@ -23,71 +47,720 @@ impl Method {
     }
 }
 
-enum RouteMatch {
+type Handler = Box<dyn Fn(&Request, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static>;
+type StreamingHandler = Box<dyn Fn(&Request, &mut Body<'_>, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static>;
+
+enum RouteHandler {
+    Buffered(Handler),
+    Streaming(StreamingHandler),
+}
+
+/// A registered route. Returned by the `add_*` methods on [`Router`] to configure per-route options.
+pub struct Route {
+    method: Method,
+    pattern: String,
+    handler: RouteHandler,
+    body_limit: u64,
+    middleware: Vec<Arc<dyn Middleware>>,
+    name: Option<String>,
+    accepts: Vec<String>,
+    produces: Vec<String>,
+    deadline: Option<Duration>,
+    decode_bodies: bool,
+    doc: OperationDoc,
+}
+
+impl Route {
+    fn new(method: Method, pattern: String, handler: RouteHandler) -> Self {
+        let body_limit = match handler {
+            RouteHandler::Buffered(_) => MAX_BODY_BYTES as u64,
+            RouteHandler::Streaming(_) => u64::MAX,
+        };
+        Self { method, pattern, handler, body_limit, middleware: Vec::new(), name: None, accepts: Vec::new(), produces: Vec::new(), deadline: None, decode_bodies: true, doc: OperationDoc::default() }
+    }
+
+    /// Name this route so URLs for it can be built with [`Router::url_for`] or
+    /// [`Request::url_for`]. Names should be unique; the first route with a name wins.
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Largest request body accepted, in bytes.
+    ///
+    /// Defaults to [`MAX_BODY_BYTES`] for buffered routes and unlimited for streaming routes.
+    /// Larger bodies are answered with `413 Payload Too Large`.
+    pub fn body_limit(&mut self, bytes: u64) -> &mut Self {
+        self.body_limit = bytes;
+        self
+    }
+
+    /// Run `middleware` around this route's handler, inside any router-wide middleware.
+    /// Middleware added first runs first.
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Only take request bodies of these media types, e.g. `application/json` or `image/*`.
+    /// Other bodies are answered with `415 Unsupported Media Type` before they are read; requests
+    /// without a body are not checked.
+    pub fn accepts(&mut self, media_types: &[&str]) -> &mut Self {
+        self.accepts = media_types.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    /// The media types this route can respond with, preferred first. The one the client's
+    /// `Accept` header favours is put in [`Request::response_type`] for the handler; if it accepts
+    /// none of them the request is answered with `406 Not Acceptable`.
+    pub fn produces(&mut self, media_types: &[&str]) -> &mut Self {
+        self.produces = media_types.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    /// Answer with `503 Service Unavailable` and close the connection if the request is not
+    /// finished within `limit` of its head arriving, body reading included.
+    ///
+    /// The handler is not interrupted: it keeps running on its thread, but can no longer use the
+    /// connection, and can check [`Request::remaining`] to give up early. If it has already begun
+    /// its response, the connection is just closed.
+    pub fn deadline(&mut self, limit: Duration) -> &mut Self {
+        self.deadline = Some(limit);
+        self
+    }
+
+    /// Whether request bodies sent with `Content-Encoding: gzip` or `deflate` are decompressed
+    /// before the handler sees them. On by default when the `compression` feature is enabled,
+    /// in which case [`body_limit`](Self::body_limit) applies to the decompressed size and other
+    /// encodings are answered with `415 Unsupported Media Type`. Turn it off to pass bodies on
+    /// as they were sent, e.g. to a [`Proxy`](crate::Proxy).
+    pub fn decode_bodies(&mut self, decode: bool) -> &mut Self {
+        self.decode_bodies = decode;
+        self
+    }
+
+    /// One-line description for the [OpenAPI document](crate::openapi::OpenApi).
+    pub fn summary(&mut self, summary: &str) -> &mut Self {
+        self.doc.summary = Some(summary.to_string());
+        self
+    }
+
+    /// JSON Schema of the request body, for the OpenAPI document.
+    pub fn request_schema(&mut self, schema: &str) -> &mut Self {
+        self.doc.request_schema = Some(schema.to_string());
+        self
+    }
+
+    /// Document a possible response, with a JSON Schema of its body if it has one.
+    pub fn response(&mut self, status: u16, description: &str, schema: Option<&str>) -> &mut Self {
+        self.doc.responses.push((status, description.to_string(), schema.map(str::to_string)));
+        self
+    }
+
+    /// Document that the route requires the named security scheme; see
+    /// [`OpenApi::security_scheme`](crate::openapi::OpenApi::security_scheme). Enforcing it is
+    /// still up to the handler or middleware.
+    pub fn auth(&mut self, scheme: &str) -> &mut Self {
+        self.doc.security.push(scheme.to_string());
+        self
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// The pattern this route was registered with; prefix routes end in `*`, e.g. `/api/notes/*`.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub(crate) fn limit(&self) -> u64 {
+        self.body_limit
+    }
+
+    pub(crate) fn route_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(crate) fn accepted_types(&self) -> &[String] {
+        &self.accepts
+    }
+
+    pub(crate) fn produced_types(&self) -> &[String] {
+        &self.produces
+    }
+
+    pub(crate) fn doc(&self) -> &OperationDoc {
+        &self.doc
+    }
+
+    pub(crate) fn time_limit(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    #[cfg_attr(not(feature = "compression"), allow(dead_code))]
+    pub(crate) fn decodes_bodies(&self) -> bool {
+        self.decode_bodies
+    }
+
+    pub(crate) fn is_streaming(&self) -> bool {
+        matches!(self.handler, RouteHandler::Streaming(_))
+    }
+
+    /// Run the handler. For buffered routes `body` has already been read into `req.body`.
+    pub(crate) fn call(&self, req: &Request, body: &mut Body<'_>, stream: &mut dyn Write) -> io::Result<()> {
+        match &self.handler {
+            RouteHandler::Buffered(handler) => handler(req, stream),
+            RouteHandler::Streaming(handler) => handler(req, body, stream),
+        }
+    }
+}
+
+/// Patterns of named routes, for building URLs.
+#[derive(Debug, Default)]
+pub(crate) struct RouteNames(HashMap<String, String>);
+
+impl RouteNames {
+    /// Fill in `pattern`'s `:param` and `*wildcard` segments from `params`, percent-encoding the
+    /// values; parameters the pattern does not use become the query string.
+    pub(crate) fn url_for(&self, name: &str, params: &[(&str, &str)]) -> io::Result<String> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let pattern = self.0.get(name).ok_or_else(|| invalid(format!("no route named `{}`", name)))?;
+        let mut used = vec![false; params.len()];
+        let mut value = |param: &str| match params.iter().position(|(n, v)| *n == param && !v.is_empty()) {
+            Some(i) => {
+                used[i] = true;
+                Ok(params[i].1)
+            }
+            None => Err(invalid(format!("route `{}` needs a non-empty `{}` parameter", name, param))),
+        };
+
+        let mut url = String::new();
+        for segment in pattern.split('/').skip(1) {
+            url.push('/');
+            if let Some(param) = segment.strip_prefix(':') {
+                url.push_str(&percent_encode(value(param)?));
+            } else if let Some(param) = segment.strip_prefix('*') {
+                // A prefix route's unnamed wildcard builds the prefix itself.
+                if !param.is_empty() {
+                    url.push_str(&value(param)?.split('/').map(percent_encode).collect::<Vec<_>>().join("/"));
+                }
+            } else {
+                url.push_str(segment);
+            }
+        }
+        let query: Vec<String> = params.iter().zip(&used).filter(|(_, used)| !**used).map(|((n, v), _)| format!("{}={}", percent_encode(n), percent_encode(v))).collect();
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query.join("&"));
+        }
+        Ok(url)
+    }
+}
+
+/// Encode everything but RFC 3986 unreserved characters.
+fn percent_encode(value: &str) -> String {
+    value.bytes().map(|b| if b.is_ascii_alphanumeric() || b"-._~".contains(&b) { (b as char).to_string() } else { format!("%{:02X}", b) }).collect()
+}
+
+/// Decode `%XX` escapes in a captured path segment; `None` if the result is not UTF-8. Unlike
+/// in query strings, `+` stays as it is, and malformed escapes are kept literally.
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        match (bytes[i], bytes.get(i + 1).copied().and_then(hex), bytes.get(i + 2).copied().and_then(hex)) {
+            (b'%', Some(hi), Some(lo)) => {
+                out.push((hi * 16 + lo) as u8);
+                i += 3;
+            }
+            (b, _, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Why a request is not handed to a route's handler.
+#[derive(Clone, Copy)]
+pub(crate) enum Unrouted {
+    Options,
+    MethodNotAllowed,
+    NotFound,
+    /// An HTTP/1.1 request without `Host` to a router with virtual hosts.
+    MissingHost,
+    /// A captured path parameter is not UTF-8 once percent-decoded.
+    BadPathEncoding,
+    /// The body's `Content-Type` is not one the route [accepts](Route::accepts).
+    UnsupportedMediaType,
+    /// `Accept` allows none of the types the route [produces](Route::produces).
+    NotAcceptable,
+}
+
+impl Unrouted {
+    /// Write the built-in response for an unrouted request.
+    pub(crate) fn respond(self, req: &Request, stream: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Unrouted::Options => write_response(stream, 204, "No Content", "text/plain", b""),
+            Unrouted::MethodNotAllowed => req.send_error(stream, HttpError::new(405, "method not allowed")),
+            Unrouted::NotFound => req.send_error(stream, HttpError::new(404, "not found")),
+            Unrouted::MissingHost => req.send_error(stream, HttpError::new(400, "missing host")),
+            Unrouted::BadPathEncoding => req.send_error(stream, HttpError::new(400, "path is not valid UTF-8")),
+            Unrouted::UnsupportedMediaType => req.send_error(stream, HttpError::new(415, "unsupported media type")),
+            Unrouted::NotAcceptable => req.send_error(stream, HttpError::new(406, "not acceptable")),
+        }
+    }
+}
+
+/// A `Host` pattern for [`Router::host`].
+enum HostPattern {
     Exact(String),
-    Prefix(String),
+    /// `*.example.com`, stored as `.example.com`.
+    Subdomains(String),
 }
 
-impl RouteMatch {
-    fn matches(&self, path: &str) -> bool {
+impl HostPattern {
+    fn parse(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => HostPattern::Subdomains(suffix.to_string()),
+            _ => HostPattern::Exact(pattern),
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
         match self {
-            RouteMatch::Exact(expected) => path == expected,
-            RouteMatch::Prefix(prefix) => path.starts_with(prefix),
+            HostPattern::Exact(name) => host == name,
+            HostPattern::Subdomains(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
         }
     }
 }
 
-struct Route {
-    method: Method,
-    matcher: RouteMatch,
-    handler: Box<dyn Fn(&Request, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static>,
+/// The request's `Host` header without its port, lower-cased and without a trailing dot.
+fn request_host(req: &Request) -> Option<String> {
+    let host = req.header("host")?.trim();
+    let name = match host.strip_prefix('[') {
+        Some(rest) => &host[..rest.find(']').map_or(host.len(), |end| end + 2)],
+        None => host.split(':').next().unwrap_or(host),
+    };
+    Some(name.trim_end_matches('.').to_ascii_lowercase())
+}
+
+fn prefix_pattern(prefix: &str) -> String {
+    if prefix.ends_with('/') { format!("{}*", prefix) } else { format!("{}/*", prefix) }
+}
+
+/// Check the request against the route's [`accepts`](Route::accepts) and
+/// [`produces`](Route::produces) lists, choosing `req.response_type`.
+fn negotiate<'r>(route: &'r Route, req: &mut Request) -> Result<&'r Route, Unrouted> {
+    let has_body = req.header("content-type").is_some() || !matches!(Framing::from_request(req), Ok(Framing::Length(0)));
+    if !route.accepts.is_empty() && has_body {
+        let content_type = req.header("content-type").unwrap_or("");
+        if !route.accepts.iter().any(|range| negotiation::media_type_matches(range, content_type)) {
+            return Err(Unrouted::UnsupportedMediaType);
+        }
+    }
+    if !route.produces.is_empty() {
+        let offered: Vec<&str> = route.produces.iter().map(String::as_str).collect();
+        let chosen = negotiation::preferred_type(req.header("accept"), &offered).ok_or(Unrouted::NotAcceptable)?;
+        req.response_type = Some(chosen.to_string());
+    }
+    Ok(route)
 }
 
+/// Where a request was routed, and the routers it passed through on the way.
+pub(crate) struct Dispatch<'r> {
+    router: &'r Router,
+    /// The virtual host selected by `Host`, if any.
+    host: Option<&'r Router>,
+    pub(crate) target: Result<&'r Route, Unrouted>,
+}
+
+impl Dispatch<'_> {
+    /// Run the middleware chain and then the route's handler, or the built-in response for an
+    /// unrouted request.
+    pub(crate) fn run(self, req: &Request, body: &mut Body<'_>, stream: &mut dyn Write) -> io::Result<()> {
+        let host = self.host.map_or(&[][..], |h| &h.middleware[..]);
+        let fallback = self.host.unwrap_or(self.router).fallback.as_ref();
+        match (self.target, fallback) {
+            (Ok(route), _) => Next::new(&[&self.router.middleware, host, &route.middleware], &mut |req, stream| route.call(req, body, stream)).run(req, stream),
+            (Err(Unrouted::NotFound), Some(fallback)) => Next::new(&[&self.router.middleware, host], &mut |req, stream| fallback(req, stream)).run(req, stream),
+            (Err(unrouted), _) => Next::new(&[&self.router.middleware, host], &mut |req, stream| unrouted.respond(req, stream)).run(req, stream),
+        }
+    }
+}
+
+/// Routes requests by method and path.
+///
+/// Paths are matched segment by segment, ignoring any query string. A pattern segment is either
+/// literal text, a `:name` parameter matching any one non-empty segment, or, as the last
+/// segment, a `*name` wildcard matching the rest of the path. Captured values are available
+/// from [`Request::param`]. Literal segments take precedence over parameters, and parameters
+/// over wildcards, regardless of the order routes are added in.
 pub struct Router {
     routes: Vec<Route>,
+    trees: HashMap<Method, Node>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    hosts: Vec<(HostPattern, Router)>,
+    fallback: Option<Handler>,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
+    /// Built on first use; reset whenever routes are added.
+    names: OnceLock<Arc<RouteNames>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Router {
+    /// Routes in the order they were added, excluding those of virtual hosts.
+    pub(crate) fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
     /// Create a new, empty router.
     pub fn new() -> Self {
-        Self { routes: Vec::new() }
+        Self { routes: Vec::new(), trees: HashMap::new(), middleware: Vec::new(), hosts: Vec::new(), fallback: None, error_handler: None, names: OnceLock::new() }
     }
 
-    /// Add an exact-match route for `path`.
-    pub fn add_route<F>(&mut self, method: Method, path: &str, handler: F)
+    /// Add a route for `path`, which may contain `:param` and `*wildcard` segments.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is malformed or conflicts with a route already added for `method`: the same
+    /// path, or a parameter with a different name at the same position.
+    pub fn add_route<F>(&mut self, method: Method, path: &str, handler: F) -> &mut Route
     where
         F: Fn(&Request, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
     {
-        self.routes.push(Route { method, matcher: RouteMatch::Exact(path.to_string()), handler: Box::new(handler) });
+        self.push(Route::new(method, path.to_string(), RouteHandler::Buffered(Box::new(handler))))
     }
 
-    /// Add a prefix route that matches paths starting with `prefix`.
-    pub fn add_prefix_route<F>(&mut self, method: Method, prefix: &str, handler: F)
+    /// Add a route for `prefix` and every path below it; `/api/notes/` is the same as
+    /// `/api/notes/*`. A missing trailing `/` is added.
+    pub fn add_prefix_route<F>(&mut self, method: Method, prefix: &str, handler: F) -> &mut Route
     where
         F: Fn(&Request, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
     {
-        self.routes.push(Route { method, matcher: RouteMatch::Prefix(prefix.to_string()), handler: Box::new(handler) });
+        self.push(Route::new(method, prefix_pattern(prefix), RouteHandler::Buffered(Box::new(handler))))
     }
 
-    /// Handle a parsed request by dispatching to the first matching route.
-    pub fn handle(&self, req: Request, stream: &mut dyn Write) -> io::Result<()> {
-        if req.method == "OPTIONS" {
-            return write_response(stream, 204, "No Content", "text/plain", b"");
-        }
+    /// Add a route whose handler reads the request body itself.
+    ///
+    /// `req.body` is empty; the body is read from the [`Body`] argument as it arrives.
+    pub fn add_streaming_route<F>(&mut self, method: Method, path: &str, handler: F) -> &mut Route
+    where
+        F: Fn(&Request, &mut Body<'_>, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.push(Route::new(method, path.to_string(), RouteHandler::Streaming(Box::new(handler))))
+    }
+
+    /// Add a streaming prefix route; see [`add_streaming_route`](Self::add_streaming_route).
+    pub fn add_streaming_prefix_route<F>(&mut self, method: Method, prefix: &str, handler: F) -> &mut Route
+    where
+        F: Fn(&Request, &mut Body<'_>, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.push(Route::new(method, prefix_pattern(prefix), RouteHandler::Streaming(Box::new(handler))))
+    }
+
+    /// Run `middleware` around every request, including those that match no route.
+    /// Middleware added first runs first.
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Serve requests whose `Host` matches `pattern` from `router` instead of this router's own
+    /// routes, which remain the fallback for other hosts.
+    ///
+    /// `pattern` is a host name such as `docs.example.com`, or `*.example.com` to match any
+    /// subdomain (but not `example.com` itself); the port in `Host` is ignored. Patterns are
+    /// tried in the order added. Once a host is added, HTTP/1.1 requests without `Host` are
+    /// answered with `400 Bad Request`. This router's middleware runs before `router`'s.
+    pub fn host(&mut self, pattern: &str, router: Router) -> &mut Self {
+        self.names = OnceLock::new();
+        self.hosts.push((HostPattern::parse(pattern), router));
+        self
+    }
+
+    /// Handle requests whose path matches no route, instead of answering `404 Not Found`.
+    pub fn fallback<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Render every error response for requests routed here, built-in or sent with
+    /// [`Request::send_error`], with `handler`. Defaults to [`JsonErrors`](crate::JsonErrors).
+    ///
+    /// A virtual host without its own handler uses this router's.
+    pub fn error_handler(&mut self, handler: impl ErrorHandler + 'static) -> &mut Self {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// The handler for errors not tied to a routed request, such as unparsable heads.
+    pub(crate) fn errors(&self) -> SharedErrorHandler {
+        self.error_handler.clone().map_or_else(SharedErrorHandler::default, SharedErrorHandler)
+    }
+
+    /// Build the URL of the route named `name`, filling in its `:param` and `*wildcard` segments
+    /// from `params` (percent-encoded). Parameters the pattern does not use are added as the query
+    /// string. Named routes of virtual hosts are included, after this router's own.
+    ///
+    /// Fails with `InvalidInput` if there is no such route or a parameter is missing or empty.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> io::Result<String> {
+        self.names().url_for(name, params)
+    }
 
-        let method = match Method::from_str(&req.method) {
-            Some(method) => method,
-            None => {
-                return write_response(stream, 405, "Method Not Allowed", "application/json", b"{\"error\":\"method not allowed\"}");
+    fn names(&self) -> Arc<RouteNames> {
+        let names = self.names.get_or_init(|| {
+            let mut names = HashMap::new();
+            let hosts = self.hosts.iter().flat_map(|(_, router)| router.names().0.clone());
+            for (name, pattern) in self.routes.iter().filter_map(|r| Some((r.name.clone()?, r.pattern.clone()))).chain(hosts) {
+                names.entry(name).or_insert(pattern);
             }
+            Arc::new(RouteNames(names))
+        });
+        Arc::clone(names)
+    }
+
+    fn push(&mut self, route: Route) -> &mut Route {
+        self.names = OnceLock::new();
+        let tree = self.trees.entry(route.method).or_default();
+        if let Err(conflict) = tree.insert(&route.pattern, self.routes.len()) {
+            panic!("cannot add route {} {}: {}", route.method.as_str(), route.pattern, conflict);
+        }
+        self.routes.push(route);
+        self.routes.last_mut().expect("route was just pushed")
+    }
+
+    /// Select the virtual host for `req`, then the route for its method and path, storing any
+    /// captured parameters in `req.params`.
+    pub(crate) fn dispatch(&self, req: &mut Request) -> Dispatch<'_> {
+        req.error_handler = self.errors();
+        req.route_names = self.names();
+        let host = match request_host(req) {
+            _ if self.hosts.is_empty() => None,
+            Some(name) => self.hosts.iter().find(|(pattern, _)| pattern.matches(&name)).map(|(_, router)| router),
+            None if req.version == "HTTP/1.1" => return Dispatch { router: self, host: None, target: Err(Unrouted::MissingHost) },
+            None => None,
+        };
+        if let Some(handler) = host.and_then(|h| h.error_handler.clone()) {
+            req.error_handler = SharedErrorHandler(handler);
+        }
+        let mut params = Vec::new();
+        let target = host.unwrap_or(self).find(&req.method, &req.path, &mut params).and_then(|route| negotiate(route, req));
+        req.params = params;
+        Dispatch { router: self, host, target }
+    }
+
+    /// Find the most specific route for the request method and path.
+    fn find(&self, method: &str, path: &str, params: &mut Vec<(String, String)>) -> Result<&Route, Unrouted> {
+        if method == "OPTIONS" {
+            return Err(Unrouted::Options);
+        }
+        let method = Method::from_str(method).ok_or(Unrouted::MethodNotAllowed)?;
+        let path = path.split('?').next().unwrap_or(path);
+        let segments = tree::segments(path).ok_or(Unrouted::NotFound)?;
+        let index = self.trees.get(&method).and_then(|tree| tree.find(&segments, params)).ok_or(Unrouted::NotFound)?;
+        for (_, value) in params.iter_mut() {
+            *value = percent_decode(value).ok_or(Unrouted::BadPathEncoding)?;
+        }
+        Ok(&self.routes[index])
+    }
+
+    /// Handle a parsed request by dispatching to the most specific matching route: at each path
+    /// segment a static segment beats a `:param`, which beats a `*wildcard`, whatever order the
+    /// routes were added in. Routes that could match the same paths equally well are rejected
+    /// when they are added.
+    ///
+    /// Streaming routes read from the already-buffered `req.body`, decoded first if it was sent
+    /// compressed.
+    pub fn handle(&self, mut req: Request, stream: &mut dyn Write) -> io::Result<()> {
+        let dispatch = self.dispatch(&mut req);
+        #[cfg(feature = "compression")]
+        if let Ok(route) = dispatch.target
+            && route.decodes_bodies()
+            && let Err(e) = crate::encoding::decode_buffered(&mut req, route.limit())
+        {
+            return req.send_error(stream, e);
+        }
+        let (buffered, limit) = match dispatch.target {
+            Ok(route) if route.is_streaming() => (std::mem::take(&mut req.body), route.limit()),
+            _ => (Vec::new(), 0),
+        };
+        let len = buffered.len() as u64;
+        dispatch.run(&req, &mut Body::new(&mut Cursor::new(buffered), Framing::Length(len), limit), stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_response_with_headers;
+
+    #[test]
+    fn router_selects_virtual_host() {
+        let site = |name: &'static str| {
+            let mut router = Router::new();
+            router.add_route(Method::Get, "/", move |_req, stream| write_response(stream, 200, "OK", "text/plain", name.as_bytes()));
+            router
         };
+        let mut router = site("default");
+        router.host("docs.example.com", site("docs")).host("*.example.com", site("tenant"));
 
-        for route in &self.routes {
-            if route.method == method && route.matcher.matches(&req.path) {
-                return (route.handler)(&req, stream);
+        let get = |host: Option<&str>, version: &str| {
+            let mut req = Request::new("GET", "/");
+            req.version = version.to_string();
+            if let Some(host) = host {
+                req.headers.insert("host".to_string(), host.to_string());
             }
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(get(Some("docs.example.com"), "HTTP/1.1").ends_with("\r\n\r\ndocs"));
+        assert!(get(Some("Docs.Example.com.:8080"), "HTTP/1.1").ends_with("\r\n\r\ndocs"));
+        assert!(get(Some("a.b.example.com"), "HTTP/1.1").ends_with("\r\n\r\ntenant"));
+        assert!(get(Some("example.com"), "HTTP/1.1").ends_with("\r\n\r\ndefault"));
+        assert!(get(Some("[::1]:8080"), "HTTP/1.1").ends_with("\r\n\r\ndefault"));
+        assert!(get(None, "HTTP/1.0").ends_with("\r\n\r\ndefault"));
+        let missing = get(None, "HTTP/1.1");
+        assert!(missing.starts_with("HTTP/1.1 400 Bad Request"), "{}", missing);
+        assert!(missing.ends_with("{\"error\":\"missing host\"}"));
+    }
+
+    #[test]
+    fn router_prefers_specific_routes_regardless_of_order() {
+        let mut router = Router::new();
+        let reply = |name: &'static str| move |req: &Request, stream: &mut dyn std::io::Write| write_response(stream, 200, "OK", "text/plain", format!("{} {:?}", name, req.params).as_bytes());
+        router.add_prefix_route(Method::Get, "/api/notes/", reply("prefix"));
+        router.add_route(Method::Get, "/api/notes/:id", reply("param"));
+        router.add_route(Method::Get, "/api/notes/:id/changes", reply("changes"));
+        router.add_route(Method::Get, "/api/notes/search", reply("search"));
+        router.add_route(Method::Get, "/files/*path", reply("files"));
+
+        let get = |path: &str| {
+            let mut out = Vec::new();
+            router.handle(Request::new("GET", path), &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            out.split("\r\n\r\n").nth(1).unwrap_or(&out).to_string()
+        };
+        assert_eq!(get("/api/notes/search?q=x"), "search []");
+        assert_eq!(get("/api/notes/42"), "param [(\"id\", \"42\")]");
+        assert_eq!(get("/api/notes/42/changes"), "changes [(\"id\", \"42\")]");
+        assert_eq!(get("/api/notes/42/other"), "prefix []");
+        assert_eq!(get("/api/notes/"), "prefix []");
+        assert_eq!(get("/files/a/b.txt"), "files [(\"path\", \"a/b.txt\")]");
+        assert_eq!(get("/files"), "{\"error\":\"not found\"}");
+    }
+
+    #[test]
+    fn router_decodes_captured_parameters() {
+        let mut router = Router::new();
+        let reply = |req: &Request, stream: &mut dyn std::io::Write| write_response(stream, 200, "OK", "text/plain", format!("{:?}", req.params).as_bytes());
+        router.add_route(Method::Get, "/notes/:id", reply).name("note");
+        router.add_route(Method::Get, "/files/*path", reply).name("file");
+
+        let get = |path: &str| {
+            let mut out = Vec::new();
+            router.handle(Request::new("GET", path), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        for (name, param, value) in [("note", "id", "a b/c?d&e=%f+ü"), ("file", "path", "dir/ünï code.md")] {
+            let url = router.url_for(name, &[(param, value)]).unwrap();
+            assert!(get(&url).ends_with(&format!("\r\n\r\n{:?}", [(param, value)])), "{}", url);
         }
+        // `+` is only a space in query strings, and malformed escapes are kept.
+        assert!(get("/notes/a+b%2").ends_with("[(\"id\", \"a+b%2\")]"));
+        assert!(get("/notes/%FF").starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
 
-        write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}")
+    #[test]
+    #[should_panic(expected = "cannot add route GET /notes/:note_id: `:note_id` conflicts with `:id` at the same position")]
+    fn router_rejects_conflicting_routes() {
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/notes/:id", |_req, _stream| Ok(()));
+        router.add_route(Method::Post, "/notes/:note_id", |_req, _stream| Ok(()));
+        router.add_route(Method::Get, "/notes/:note_id", |_req, _stream| Ok(()));
+    }
+
+    #[test]
+    fn router_fallback_handles_unmatched_paths() {
+        let mut api = Router::new();
+        api.error_handler(|_req: Option<&Request>, error: &HttpError| ("text/plain".to_string(), format!("{}: {}", error.status, error.message).into_bytes()));
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/notes", |_req, stream| write_response(stream, 200, "OK", "text/plain", b"notes"));
+        router.fallback(|req, stream| write_response(stream, 200, "OK", "text/html", format!("<app {}>", req.path).as_bytes()));
+        router.host("api.example.com", api);
+
+        let get = |method: &str, path: &str, host: &str| {
+            let mut req = Request::new(method, path);
+            req.headers.insert("host".to_string(), host.to_string());
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(get("GET", "/notes", "example.com").ends_with("\r\n\r\nnotes"));
+        assert!(get("GET", "/settings/profile", "example.com").ends_with("\r\n\r\n<app /settings/profile>"));
+        // Wrong methods on known paths are still errors, not the fallback.
+        assert!(get("TRACE", "/notes", "example.com").starts_with("HTTP/1.1 405 "));
+        // The virtual host has no fallback of its own but renders errors its own way.
+        let api = get("GET", "/missing", "api.example.com");
+        assert!(api.starts_with("HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n"), "{}", api);
+        assert!(api.ends_with("404: not found"));
+    }
+
+    #[test]
+    fn router_builds_urls_for_named_routes() {
+        let mut docs = Router::new();
+        docs.add_route(Method::Get, "/pages/*path", |_req, _stream| Ok(())).name("page");
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/notes/:id", |req, stream| write_response(stream, 200, "OK", "text/plain", req.param("id").unwrap_or("").as_bytes())).name("note");
+        router.add_prefix_route(Method::Get, "/static/", |_req, _stream| Ok(())).name("static");
+        router
+            .add_route(Method::Post, "/notes", |req, stream| {
+                let id = String::from_utf8_lossy(&req.body);
+                let location = req.url_for("note", &[("id", &id)])?;
+                let link = format!("<{}>; rel=\"self\"", location);
+                write_response_with_headers(stream, 201, "Created", "text/plain", &[("Location", &location), ("Link", &link)], b"")
+            })
+            .name("notes");
+        router.host("docs.example.com", docs);
+
+        assert_eq!(router.url_for("note", &[("id", "a b/c?")]).unwrap(), "/notes/a%20b%2Fc%3F");
+        assert_eq!(router.url_for("note", &[("id", "7"), ("view", "full & more")]).unwrap(), "/notes/7?view=full%20%26%20more");
+        assert_eq!(router.url_for("static", &[]).unwrap(), "/static/");
+        assert_eq!(router.url_for("page", &[("path", "guide/ünï code.md")]).unwrap(), "/pages/guide/%C3%BCn%C3%AF%20code.md");
+        assert_eq!(router.url_for("note", &[]).unwrap_err().to_string(), "route `note` needs a non-empty `id` parameter");
+        assert_eq!(router.url_for("note", &[("id", "")]).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(router.url_for("nope", &[]).unwrap_err().to_string(), "no route named `nope`");
+
+        let handle = |req: Request| {
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        for (id, path) in [("42", "/notes/42"), ("a/b?c#d;e> f", "/notes/a%2Fb%3Fc%23d%3Be%3E%20f")] {
+            let mut req = Request::new("POST", "/notes");
+            req.headers.insert("host".to_string(), "example.com".to_string());
+            req.body = id.as_bytes().to_vec();
+            let out = handle(req);
+            assert!(out.contains(&format!("Location: {}\r\n", path)), "{}", out);
+            assert!(out.contains(&format!("Link: <{}>; rel=\"self\"\r\n", path)), "{}", out);
+            // Following the link leads back to the same id.
+            let mut req = Request::new("GET", path);
+            req.headers.insert("host".to_string(), "example.com".to_string());
+            assert!(handle(req).ends_with(&format!("\r\n\r\n{}", id)));
+        }
     }
 }
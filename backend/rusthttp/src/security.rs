@@ -0,0 +1,255 @@
+//! Browser-facing hardening: security response headers and CSRF protection.
+
+use std::io::{self, Write};
+
+use crate::error::HttpError;
+use crate::middleware::{Middleware, Next};
+use crate::request::Request;
+use crate::response::InjectHeaders;
+
+/// Middleware adding security headers to every response that does not already set them.
+///
+/// The defaults suit an API or a same-origin app:
+///
+/// | Header | Default |
+/// |---|---|
+/// | `Content-Security-Policy` | `default-src 'self'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'` |
+/// | `X-Content-Type-Options` | `nosniff` |
+/// | `Referrer-Policy` | `strict-origin-when-cross-origin` |
+/// | `X-Frame-Options` | `DENY` |
+/// | `Strict-Transport-Security` | `max-age=31536000; includeSubDomains`, on HTTPS connections only |
+///
+/// A handler that writes one of these headers itself keeps its own value.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    headers: Vec<(String, String)>,
+    hsts: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecurityHeaders {
+    pub fn new() -> Self {
+        let headers = [
+            ("Content-Security-Policy", "default-src 'self'; frame-ancestors 'none'; base-uri 'self'; form-action 'self'"),
+            ("X-Content-Type-Options", "nosniff"),
+            ("Referrer-Policy", "strict-origin-when-cross-origin"),
+            ("X-Frame-Options", "DENY"),
+        ];
+        Self { headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(), hsts: Some("max-age=31536000; includeSubDomains".to_string()) }
+    }
+
+    pub fn content_security_policy(self, policy: &str) -> Self {
+        self.header("Content-Security-Policy", policy)
+    }
+
+    pub fn referrer_policy(self, policy: &str) -> Self {
+        self.header("Referrer-Policy", policy)
+    }
+
+    /// `DENY` or `SAMEORIGIN`.
+    pub fn frame_options(self, value: &str) -> Self {
+        self.header("X-Frame-Options", value)
+    }
+
+    /// Value of `Strict-Transport-Security`, still only sent over HTTPS; `None` to never send it.
+    pub fn strict_transport_security(mut self, value: Option<&str>) -> Self {
+        self.hsts = value.map(str::to_string);
+        self
+    }
+
+    /// Set any other header, replacing an earlier value for the same name.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self = self.without(name);
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Stop sending `name`, e.g. `X-Frame-Options` for a page meant to be embedded.
+    pub fn without(mut self, name: &str) -> Self {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        if name.eq_ignore_ascii_case("strict-transport-security") {
+            self.hsts = None;
+        }
+        self
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        let mut headers: Vec<(&str, &str)> = self.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        // Browsers ignore HSTS over plain HTTP (RFC 6797 section 7.2).
+        if let Some(hsts) = &self.hsts
+            && req.connection.is_secure()
+        {
+            headers.push(("Strict-Transport-Security", hsts));
+        }
+        let mut writer = InjectHeaders::new(stream, &headers).if_absent();
+        next.run(req, &mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Middleware rejecting cross-site `POST`, `PUT`, `PATCH` and `DELETE` requests that carry
+/// cookies, with `403 Forbidden`.
+///
+/// A request passes if its `Origin` matches its `Host` or a [trusted origin](Self::trusted_origin),
+/// or, without a matching `Origin`, if `Sec-Fetch-Site` says it came from the same origin or
+/// was typed by the user. Requests with neither header are from non-browser clients (or very old
+/// browsers) and pass, unless a [double-submit token](Self::double_submit) is required. Requests
+/// without cookies are not checked: only cookies are sent by browsers on their own.
+#[derive(Clone, Debug, Default)]
+pub struct Csrf {
+    trusted_origins: Vec<String>,
+    allow_same_site: bool,
+    double_submit: Option<(String, String)>,
+}
+
+impl Csrf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept requests from `origin`, e.g. `https://app.example.com`, such as a frontend served
+    /// from another port or subdomain.
+    pub fn trusted_origin(mut self, origin: &str) -> Self {
+        self.trusted_origins.push(origin.trim_end_matches('/').to_ascii_lowercase());
+        self
+    }
+
+    /// Also accept `Sec-Fetch-Site: same-site`, i.e. any subdomain or port of this site.
+    pub fn allow_same_site(mut self) -> Self {
+        self.allow_same_site = true;
+        self
+    }
+
+    /// Additionally require the request header `header` to repeat the value of the cookie
+    /// `cookie`. The application sets that cookie to a random value readable by its own scripts;
+    /// another site can make the browser send the cookie but cannot read it to copy it.
+    pub fn double_submit(mut self, cookie: &str, header: &str) -> Self {
+        self.double_submit = Some((cookie.to_string(), header.to_string()));
+        self
+    }
+
+    fn same_origin(&self, req: &Request, origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        if self.trusted_origins.contains(&origin) {
+            return true;
+        }
+        let authority = origin.split_once("://").map_or("", |(_, authority)| authority);
+        req.header("host").is_some_and(|host| !authority.is_empty() && host.eq_ignore_ascii_case(authority))
+    }
+
+    fn check(&self, req: &Request) -> Result<(), &'static str> {
+        let origin = req.header("origin");
+        let allowed = match (origin, req.header("sec-fetch-site")) {
+            (Some(origin), _) if self.same_origin(req, origin) => true,
+            (_, Some(site)) => site == "same-origin" || site == "none" || (self.allow_same_site && site == "same-site"),
+            (Some(_), None) => false,
+            (None, None) => true,
+        };
+        if !allowed {
+            return Err("cross-site request rejected");
+        }
+        if let Some((cookie, header)) = &self.double_submit {
+            let expected = req.cookie(cookie).unwrap_or("");
+            let sent = req.header(header).unwrap_or("");
+            if expected.is_empty() || !constant_time_eq(expected.as_bytes(), sent.as_bytes()) {
+                return Err("missing or invalid csrf token");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare without returning early, so timing does not reveal how much of a token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Middleware for Csrf {
+    fn handle(&self, req: &Request, stream: &mut dyn Write, next: Next<'_>) -> io::Result<()> {
+        let unsafe_method = !matches!(req.method.as_str(), "GET" | "HEAD" | "OPTIONS" | "TRACE");
+        if unsafe_method
+            && req.header("cookie").is_some()
+            && let Err(reason) = self.check(req)
+        {
+            return req.send_error(stream, HttpError::new(403, reason));
+        }
+        next.run(req, stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router, TlsInfo, write_response, write_response_with_headers};
+
+    #[test]
+    fn security_headers_fill_in_missing_headers() {
+        let mut router = Router::new();
+        router.wrap(SecurityHeaders::new().frame_options("SAMEORIGIN").without("Referrer-Policy"));
+        router.add_route(Method::Get, "/page", |_req, stream| write_response_with_headers(stream, 200, "OK", "text/html", &[("Content-Security-Policy", "default-src 'none'")], b"<p>hi</p>"));
+
+        let get = |path: &str, secure: bool| {
+            let mut req = Request::new("GET", path);
+            if secure {
+                req.connection.tls = Some(TlsInfo::default());
+            }
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let page = get("/page", false);
+        assert!(page.contains("X-Content-Type-Options: nosniff\r\n"));
+        assert!(page.contains("X-Frame-Options: SAMEORIGIN\r\n"));
+        assert_eq!(page.matches("Content-Security-Policy:").count(), 1);
+        assert!(page.contains("Content-Security-Policy: default-src 'none'\r\n"));
+        assert!(!page.contains("Referrer-Policy") && !page.contains("Strict-Transport-Security"));
+        assert!(page.ends_with("\r\n\r\n<p>hi</p>"));
+
+        // Built-in errors get them too, and HSTS is only sent over HTTPS.
+        let missing = get("/missing", true);
+        assert!(missing.starts_with("HTTP/1.1 404 "));
+        assert!(missing.contains("Content-Security-Policy: default-src 'self'; frame-ancestors 'none'"));
+        assert!(missing.contains("Strict-Transport-Security: max-age=31536000; includeSubDomains\r\n"));
+    }
+
+    #[test]
+    fn csrf_rejects_cross_site_cookie_requests() {
+        let mut router = Router::new();
+        router.wrap(Csrf::new().trusted_origin("http://localhost:3000"));
+        router.add_route(Method::Post, "/notes", |_req, stream| write_response(stream, 201, "Created", "text/plain", b""));
+        let mut strict = Router::new();
+        strict.wrap(Csrf::new().double_submit("csrf", "X-CSRF-Token"));
+        strict.add_route(Method::Post, "/notes", |_req, stream| write_response(stream, 201, "Created", "text/plain", b""));
+
+        let post = |router: &Router, headers: &[(&str, &str)]| {
+            let mut req = Request::new("POST", "/notes");
+            req.headers.insert("host".to_string(), "api.example.com".to_string());
+            for (name, value) in headers {
+                req.headers.insert(name.to_string(), value.to_string());
+            }
+            let mut out = Vec::new();
+            router.handle(req, &mut out).unwrap();
+            String::from_utf8(out).unwrap()[9..12].to_string()
+        };
+        let session = ("cookie", "session=abc");
+        assert_eq!(post(&router, &[("origin", "https://evil.example"), ("sec-fetch-site", "cross-site")]), "201", "no cookies, nothing to forge");
+        assert_eq!(post(&router, &[session, ("origin", "https://evil.example"), ("sec-fetch-site", "cross-site")]), "403");
+        assert_eq!(post(&router, &[session, ("origin", "https://evil.example")]), "403");
+        assert_eq!(post(&router, &[session, ("sec-fetch-site", "same-site")]), "403");
+        assert_eq!(post(&router, &[session, ("origin", "http://api.example.com"), ("sec-fetch-site", "same-origin")]), "201");
+        assert_eq!(post(&router, &[session, ("origin", "http://localhost:3000"), ("sec-fetch-site", "same-site")]), "201");
+        assert_eq!(post(&router, &[session, ("sec-fetch-site", "none")]), "201");
+        assert_eq!(post(&router, &[session]), "201");
+
+        assert_eq!(post(&strict, &[("cookie", "session=abc; csrf=t0k3n")]), "403");
+        assert_eq!(post(&strict, &[("cookie", "session=abc; csrf=t0k3n"), ("x-csrf-token", "other")]), "403");
+        assert_eq!(post(&strict, &[("cookie", "session=abc; csrf=t0k3n"), ("x-csrf-token", "t0k3n")]), "201");
+    }
+}
@@ -1,35 +1,680 @@
-use std::io;
-use std::net::TcpListener;
-use std::sync::Arc;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread;
-use crate::{Router, request::parse_http_request, response::write_response};
+use std::time::{Instant, SystemTime};
+use crate::access_log::{AccessLogEntry, AccessLogger};
+use crate::error::{ErrorHandler, HttpError, write_error};
+use crate::listener::{Accepted, Listener};
+use crate::metrics::{self, Metrics};
+use crate::router::Route;
+#[cfg(feature = "compression")]
+use crate::encoding;
+use crate::{Request, Router, TrustedProxies, body::{Body, Framing, is_too_large}, request::{ConnectionInfo, RequestParser, generate_request_id}, response::{InjectHeaders, ResponseMeter}};
 
-/// Starts an HTTP server listening on the given address, using the provided router to handle requests.
-pub fn serve(addr: &str, router: Router) -> io::Result<()> {
-    let listener = TcpListener::bind(addr)?;
-    println!("Listening on http://{}", addr);
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An HTTP server: a router plus connection-level settings.
+pub struct Server {
+    router: RouterHandle,
+    trusted_proxies: TrustedProxies,
+    access_log: Option<Box<dyn AccessLogger>>,
+    metrics: Option<Arc<Metrics>>,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::tls::TlsConfig>,
+}
+
+impl Server {
+    pub fn new(router: Router) -> Self {
+        Self {
+            router: RouterHandle(Arc::new(RwLock::new(Arc::new(router)))),
+            trusted_proxies: TrustedProxies::none(),
+            access_log: None,
+            metrics: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// A handle for replacing the router while the server runs; take it before serving.
+    pub fn router_handle(&self) -> RouterHandle {
+        self.router.clone()
+    }
+
+    /// Record every request with `logger`, e.g. [`AccessLog::stdout`](crate::AccessLog::stdout).
+    pub fn access_log(mut self, logger: impl AccessLogger + 'static) -> Self {
+        self.access_log = Some(Box::new(logger));
+        self
+    }
+
+    /// Record request and connection metrics into `metrics`; serve them with [`Metrics::handler`].
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Serve HTTPS using `config` instead of plain HTTP.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: crate::tls::TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    fn scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return "https";
+        }
+        "http"
+    }
 
-    let router = Arc::new(router);
+    /// Proxies whose forwarding headers are used to resolve [`Request::client_ip`](crate::Request::client_ip).
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Listen on `addr` and serve requests. `addr` is a TCP `host:port` or `unix:/path/to.sock`
+    /// (see [`Listener::bind`]).
+    pub fn serve(self, addr: &str) -> io::Result<()> {
+        self.serve_on(vec![Listener::bind(addr)?])
+    }
+
+    /// Serve requests from every listener, e.g. a TCP port and a Unix socket, or those from
+    /// [`Listener::from_systemd`]. Each listener gets its own accept thread.
+    ///
+    /// TLS, if configured, applies to TCP listeners; Unix sockets are served as plain HTTP since
+    /// they are meant for a local proxy. Unix-socket peers count as `127.0.0.1` when resolving
+    /// [`Request::client_ip`](crate::Request::client_ip), so trusting loopback proxies trusts them too.
+    pub fn serve_on(self, listeners: Vec<Listener>) -> io::Result<()> {
+        if listeners.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no listeners to serve on"));
+        }
+        let server = Arc::new(self);
+        let mut threads = Vec::new();
+        for listener in listeners {
+            let scheme = if matches!(listener, Listener::Tcp(_)) { server.scheme() } else { "http" };
+            println!("Listening on {}://{}", scheme, listener);
+            let server = Arc::clone(&server);
+            threads.push(thread::spawn(move || accept_loop(&listener, &server)));
+        }
+        for thread in threads {
+            let _ = thread.join();
+        }
+        Ok(())
+    }
+}
 
-    for stream in listener.incoming() {
-        let router = Arc::clone(&router);
-        match stream {
-            Ok(mut stream) => {
+/// Accept connections forever, serving each on its own thread.
+fn accept_loop(listener: &Listener, server: &Arc<Server>) {
+    loop {
+        let server = Arc::clone(server);
+        match listener.accept() {
+            Ok(Accepted::Tcp(stream)) => {
                 thread::spawn(move || {
-                    let req = match parse_http_request(&mut stream) {
-                        Ok(r) => r,
-                        Err(_) => {
-                            let _ = write_response(&mut stream, 400, "Bad Request", "application/json", b"{\"error\":\"bad request\"}");
-                            return;
-                        }
-                    };
-
-                    let _ = router.handle(req, &mut stream);
+                    let _ = serve_tcp(stream, &server);
+                });
+            }
+            #[cfg(unix)]
+            Ok(Accepted::Unix(stream)) => {
+                let path = listener.unix_path().map(|p| p.to_path_buf());
+                thread::spawn(move || {
+                    let connection = ConnectionInfo { id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed), unix_socket: path, ..ConnectionInfo::default() };
+                    let _ = counted(&server, || handle_connection(&stream, stream.try_clone().ok().map(Socket::Unix), connection, &server));
                 });
             }
             Err(e) => eprintln!("connection failed: {}", e),
         }
     }
+}
+
+/// Shared, atomically replaceable reference to a server's [`Router`].
+///
+/// Each request uses the router that was current when its head arrived, for its whole life, so
+/// after [`swap`](Self::swap) requests already in flight finish on the old router and the next
+/// ones use the new one. Connections are not dropped, and the old router is freed once its last
+/// request completes.
+///
+/// ```rust,no_run
+/// use rusthttp::{Router, Server};
+///
+/// let server = Server::new(Router::new());
+/// let routes = server.router_handle();
+/// std::thread::spawn(move || {
+///     // e.g. after a config reload:
+///     routes.swap(Router::new());
+/// });
+/// server.serve("127.0.0.1:8080")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct RouterHandle(Arc<RwLock<Arc<Router>>>);
+
+impl RouterHandle {
+    /// The router new requests are served by.
+    pub fn current(&self) -> Arc<Router> {
+        Arc::clone(&self.0.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Install `router` for new requests, returning the previous one.
+    pub fn swap(&self, router: Router) -> Arc<Router> {
+        std::mem::replace(&mut *self.0.write().unwrap_or_else(PoisonError::into_inner), Arc::new(router))
+    }
+}
+
+/// Starts an HTTP server listening on the given address, using the provided router to handle requests.
+pub fn serve(addr: &str, router: Router) -> io::Result<()> {
+    Server::new(router).serve(addr)
+}
+
+fn connection_info(tcp: &TcpStream) -> ConnectionInfo {
+    ConnectionInfo { id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed), peer_addr: tcp.peer_addr().ok(), local_addr: tcp.local_addr().ok(), ..ConnectionInfo::default() }
+}
+
+/// Serve an accepted TCP connection, performing the TLS handshake first if configured.
+pub(crate) fn serve_tcp(tcp: TcpStream, server: &Server) -> io::Result<()> {
+    counted(server, || serve_tcp_inner(tcp, server))
+}
+
+/// Run `serve` for one connection, tracking it in the connection metrics.
+fn counted(server: &Server, serve: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    if let Some(metrics) = &server.metrics {
+        metrics.connection_opened();
+    }
+    let result = serve();
+    if let Some(metrics) = &server.metrics {
+        metrics.connection_closed();
+    }
+    result
+}
+
+fn serve_tcp_inner(tcp: TcpStream, server: &Server) -> io::Result<()> {
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &server.tls {
+        let mut stream = tls.accept(tcp)?;
+        let connection = ConnectionInfo { tls: Some(stream.info()), ..connection_info(stream.tcp()) };
+        let socket = stream.tcp().try_clone().ok().map(Socket::Tcp);
+        handle_connection(&mut stream, socket, connection, server)?;
+        return stream.close();
+    }
+    handle_connection(&tcp, tcp.try_clone().ok().map(Socket::Tcp), connection_info(&tcp), server)
+}
+
+/// One half of a connection shared between the request body reader and the response writer.
+///
+/// Once the request's deadline has expired, the handler can no longer read or write.
+struct Half<'a, S> {
+    stream: &'a Mutex<S>,
+    watchdog: &'a Watchdog,
+}
+
+impl<S> Half<'_, S> {
+    fn lock(&self) -> io::Result<MutexGuard<'_, S>> {
+        let stream = self.stream.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.watchdog.expired.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"));
+        }
+        Ok(stream)
+    }
+}
+
+impl<S: Write> Half<'_, S> {
+    /// Write an interim `1xx` response. Unlike [`write`](Write::write), this leaves the response
+    /// unstarted, so an expiring deadline is still answered with `503`.
+    fn write_interim(&self, head: &[u8]) -> io::Result<()> {
+        let mut stream = self.lock()?;
+        stream.write_all(head)?;
+        stream.flush()
+    }
+}
+
+impl<S: Read> Read for Half<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut stream = self.lock()?;
+        self.watchdog.reading.store(true, Ordering::Relaxed);
+        let read = stream.read(buf);
+        self.watchdog.reading.store(false, Ordering::Relaxed);
+        read
+    }
+}
+
+impl<S: Write> Write for Half<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.lock()?.write(buf)?;
+        self.watchdog.response_started.store(true, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock()?.flush()
+    }
+}
+
+/// A second handle on a connection's socket, so it can be shut down from the watchdog thread
+/// while the handler is blocked on it.
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    fn shutdown(&self, how: Shutdown) {
+        let _ = match self {
+            Socket::Tcp(socket) => socket.shutdown(how),
+            #[cfg(unix)]
+            Socket::Unix(socket) => socket.shutdown(how),
+        };
+    }
+}
+
+/// Enforces a route's [deadline](crate::Route::deadline) from a second thread.
+#[derive(Default)]
+struct Watchdog {
+    /// Set when the request is finished, before or after the deadline.
+    finished: Mutex<bool>,
+    finished_changed: Condvar,
+    expired: AtomicBool,
+    /// Whether the handler has put any of its response on the wire.
+    response_started: AtomicBool,
+    /// Whether the handler holds the connection to wait for more of the request.
+    reading: AtomicBool,
+    /// Status and body size of the response sent when the deadline expired.
+    sent: Mutex<Option<(u16, u64)>>,
+}
+
+impl Watchdog {
+    fn finish(&self) {
+        *self.finished.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.finished_changed.notify_all();
+    }
+
+    /// Wait until `deadline` or the request finishes. On expiry, answer `503` if the handler has
+    /// not started its response, then shut the connection down.
+    fn watch<S: Write>(&self, deadline: Instant, stream: &Mutex<S>, socket: Option<&Socket>, errors: &dyn ErrorHandler, request_id: &str) {
+        let mut finished = self.finished.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if *finished {
+                return;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            finished = self.finished_changed.wait_timeout(finished, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+        }
+        // Still holding `finished`, so the request cannot complete while it is being expired.
+        // Once `expired` is set the handler can no longer take the connection, only let go of it.
+        self.expired.store(true, Ordering::Relaxed);
+        let stream = match stream.try_lock() {
+            Ok(stream) => Some(stream),
+            // A handler waiting for the rest of the request body lets go of the connection once
+            // its read is cut short.
+            Err(_) if self.reading.load(Ordering::Relaxed) && socket.is_some() => {
+                socket.inspect(|socket| socket.shutdown(Shutdown::Read));
+                Some(stream.lock().unwrap_or_else(PoisonError::into_inner))
+            }
+            // The handler is in the middle of writing; closing the socket below is all that can
+            // be done.
+            Err(_) => None,
+        };
+        if let Some(mut stream) = stream
+            && !self.response_started.load(Ordering::Relaxed)
+        {
+            let mut writer = ResponseMeter::new(InjectHeaders::new(&mut *stream, &[("X-Request-Id", request_id)]));
+            if write_error(&mut writer, errors, None, &HttpError::new(503, "deadline exceeded")).and_then(|()| writer.flush()).is_ok() {
+                *self.sent.lock().unwrap_or_else(PoisonError::into_inner) = Some((writer.status(), writer.body_bytes()));
+            }
+        }
+        if let Some(socket) = socket {
+            socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Serve a single request on `stream`, then record it in the access log.
+fn handle_connection<S: Read + Write + Send>(stream: S, socket: Option<Socket>, connection: ConnectionInfo, server: &Server) -> io::Result<()> {
+    let stream = Mutex::new(stream);
+    let watchdog = Watchdog::default();
+    let mut reader = Half { stream: &stream, watchdog: &watchdog };
+    let mut parser = RequestParser::new();
+    let peer_addr = connection.peer_addr;
+
+    let head = parser.read_head(&mut reader).map(|raw| raw.map(|raw| raw.to_request(Vec::new())));
+    let started = Instant::now();
+    let router = server.router.current();
+    let mut req = match head {
+        Ok(Some(mut req)) => {
+            req.received_at = SystemTime::now();
+            let peer_ip = match connection.peer_addr {
+                Some(peer) => Some(peer.ip()),
+                None => connection.unix_socket.as_ref().map(|_| IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            };
+            req.client_ip = peer_ip.map(|peer| server.trusted_proxies.client_ip(peer, &req));
+            req.connection = connection;
+            req.assign_request_id();
+            Some(req)
+        }
+        Ok(None) => return Ok(()),
+        Err(_) => None,
+    };
+    let request_id = req.as_ref().map_or_else(generate_request_id, |r| r.request_id.clone());
+    let mut writer = ResponseMeter::new(InjectHeaders::new(Half { stream: &stream, watchdog: &watchdog }, &[("X-Request-Id", &request_id)]));
+
+    if let Some(metrics) = &server.metrics {
+        metrics.request_started();
+    }
+    let mut route = None;
+    let result = thread::scope(|scope| {
+        // Watch the deadline from another thread once the route has set one.
+        let mut arm = |req: &Request| {
+            if let Some(deadline) = req.deadline {
+                let (stream, watchdog, socket, request_id, errors) = (&stream, &watchdog, socket.as_ref(), &request_id, req.error_handler.clone());
+                scope.spawn(move || watchdog.watch(deadline, stream, socket, &*errors.0, request_id));
+            }
+        };
+        let result = match req.as_mut() {
+            Some(req) => match panic::catch_unwind(AssertUnwindSafe(|| respond(&router, req, &mut parser, &mut reader, &mut writer, &mut route, &mut arm))) {
+                Ok(result) => result,
+                Err(payload) => {
+                    let message = payload.downcast_ref::<&str>().copied().or_else(|| payload.downcast_ref::<String>().map(String::as_str)).unwrap_or("non-string panic payload");
+                    eprintln!("handler panicked on {} {} (request id {}): {}", req.method, req.path, request_id, message);
+                    if writer.status() == 0 {
+                        req.send_error(&mut writer, HttpError::new(500, "internal server error"))
+                    } else {
+                        Ok(())
+                    }
+                }
+            },
+            None => write_error(&mut writer, &*router.errors().0, None, &HttpError::new(400, "bad request")),
+        };
+        watchdog.finish();
+        result
+    });
+    let expired = watchdog.expired.load(Ordering::Relaxed);
+    // A handler that outlived its deadline fails on its next read or write; that is expected.
+    let result = if expired { Ok(()) } else { result.and_then(|()| writer.flush()) };
+    let (status, bytes) = watchdog.sent.lock().unwrap_or_else(PoisonError::into_inner).unwrap_or((writer.status(), writer.body_bytes()));
+
+    let (method, pattern) = match route {
+        Some(route) => (route.method().as_str(), route.pattern()),
+        None => ("-", metrics::UNMATCHED),
+    };
+    if expired && let Some(req) = &req {
+        eprintln!("deadline exceeded on {} {} (request id {}); handler returned after {:?}", req.method, req.path, request_id, started.elapsed());
+    }
+    if let Some(metrics) = &server.metrics {
+        if expired {
+            metrics.deadline_exceeded(method, pattern);
+        }
+        metrics.observe(method, pattern, status, started.elapsed());
+        metrics.request_finished();
+    }
 
-    Ok(())
+    if let Some(logger) = &server.access_log {
+        let time = req.as_ref().map_or_else(SystemTime::now, |r| r.received_at);
+        let entry = AccessLogEntry { request: req.as_ref(), request_id: &request_id, peer_addr, status, bytes, latency: started.elapsed(), time };
+        logger.log(&entry);
+    }
+    result
+}
+
+/// Route `req`, read its body as the route requires, and run the handler.
+///
+/// The route is resolved from the request head before any body is read, so oversized bodies are
+/// refused from their `Content-Length` alone and `Expect: 100-continue` clients are only told to
+/// proceed once the body will be accepted. Compressed bodies are decoded as they are read.
+fn respond<'s, S: Read + Write>(router: &'s Router, req: &mut Request, parser: &mut RequestParser, reader: &mut Half<'_, S>, writer: &mut dyn Write, matched: &mut Option<&'s Route>, arm: &mut dyn FnMut(&Request)) -> io::Result<()> {
+    let dispatch = router.dispatch(req);
+    let route = match dispatch.target {
+        Ok(route) => route,
+        Err(_) => return dispatch.run(req, &mut Body::new(&mut io::empty(), Framing::Length(0), 0), writer),
+    };
+    *matched = Some(route);
+    if let Some(limit) = route.time_limit() {
+        req.deadline = Some(Instant::now() + limit);
+        arm(req);
+    }
+
+    let framing = match Framing::from_request(req) {
+        Ok(framing) => framing,
+        Err(_) => return req.send_error(writer, HttpError::new(400, "bad request")),
+    };
+    if matches!(framing, Framing::Length(len) if len > route.limit()) {
+        return req.send_error(writer, HttpError::new(413, "payload too large"));
+    }
+    #[cfg(feature = "compression")]
+    let codings = match route.decodes_bodies() && framing != Framing::Length(0) {
+        true => match encoding::request_codings(req) {
+            Ok(codings) => codings,
+            Err(e) => return req.send_error(writer, e),
+        },
+        false => Vec::new(),
+    };
+    if let Some(expect) = req.header("expect") {
+        if !expect.eq_ignore_ascii_case("100-continue") {
+            return req.send_error(writer, HttpError::new(417, "expectation failed"));
+        }
+        if req.version == "HTTP/1.1" && framing != Framing::Length(0) {
+            reader.write_interim(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+    }
+
+    let mut source = parser.body_reader(reader);
+    let mut body = Body::new(&mut source, framing, route.limit());
+    #[cfg(feature = "compression")]
+    if !codings.is_empty() {
+        for &coding in codings.iter().rev() {
+            body = body.decoded(coding);
+        }
+        encoding::strip_headers(req);
+    }
+    if !route.is_streaming() {
+        req.body = match body.read_to_vec() {
+            Ok(bytes) => bytes,
+            Err(e) if is_too_large(&e) => return req.send_error(writer, HttpError::new(413, "payload too large")),
+            Err(_) => return req.send_error(writer, HttpError::new(400, "bad request")),
+        };
+    }
+    dispatch.run(req, &mut body, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{connect, connect_server, echo_router};
+    use crate::{Client, Method, write_response};
+
+    #[test]
+    fn server_rejects_oversized_content_length_before_continue() {
+        use std::io::{Read, Write};
+        let mut client = connect(echo_router(8));
+        client.write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 100\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large"));
+        assert!(!resp.contains("100 Continue"));
+    }
+
+    #[test]
+    fn server_sends_continue_then_reads_body() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let mut client = connect(echo_router(8));
+        client.write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 100 Continue\r\n");
+        reader.read_line(&mut line).unwrap();
+
+        client.write_all(b"hello").unwrap();
+        let mut resp = String::new();
+        reader.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.ends_with("hello"));
+    }
+
+    #[test]
+    fn server_attaches_connection_metadata() {
+        use std::io::{Read, Write};
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/who", |req, stream| {
+            let body = format!("{} {:?} {} {:?}", req.connection.id > 0, req.connection.peer_addr.map(|a| a.ip()), req.sequence, req.client_ip);
+            write_response(stream, 200, "OK", "text/plain", body.as_bytes())
+        });
+        let mut client = connect(router);
+        client.write_all(b"GET /who HTTP/1.1\r\nX-Forwarded-For: 203.0.113.9\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        // The loopback peer is not trusted by default, so the header is ignored.
+        assert!(resp.ends_with("true Some(127.0.0.1) 1 Some(127.0.0.1)"), "{}", resp);
+    }
+
+    #[test]
+    fn request_ids_are_generated_and_echoed_on_builtin_responses() {
+        use std::io::{Read, Write};
+        let request = |raw: &[u8]| {
+            let mut router = Router::new();
+            router.add_route(Method::Get, "/id", |req, stream| write_response(stream, 200, "OK", "text/plain", req.request_id.as_bytes()));
+            let mut client = connect(router);
+            client.write_all(raw).unwrap();
+            let mut resp = String::new();
+            client.read_to_string(&mut resp).unwrap();
+            resp
+        };
+        let echoed = |resp: &str| resp.split("\r\n").find_map(|l| l.strip_prefix("X-Request-Id: ")).map(str::to_string).expect("request id header");
+
+        let ok = request(b"GET /id HTTP/1.1\r\n\r\n");
+        let id = echoed(&ok);
+        assert_eq!(id.len(), 24);
+        assert!(ok.ends_with(&id), "{}", ok);
+
+        // Ids with spaces or control characters are replaced rather than echoed.
+        let replaced = request(b"GET /id HTTP/1.1\r\nX-Request-Id: a b\r\n\r\n");
+        assert_ne!(echoed(&replaced), "a b");
+
+        for (raw, status) in [(&b"GET /missing HTTP/1.1\r\nX-Request-Id: r-404\r\n\r\n"[..], "404"), (b"TRACE /id HTTP/1.1\r\nX-Request-Id: r-405\r\n\r\n", "405")] {
+            let resp = request(raw);
+            assert!(resp.starts_with(&format!("HTTP/1.1 {}", status)), "{}", resp);
+            assert_eq!(echoed(&resp), format!("r-{}", status));
+        }
+        let bad = request(b"garbage\r\n\r\n");
+        assert!(bad.starts_with("HTTP/1.1 400"), "{}", bad);
+        assert_eq!(echoed(&bad).len(), 24);
+    }
+
+    #[test]
+    fn handler_panic_answers_500_with_request_id() {
+        use std::io::{Read, Write};
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/boom", |_req, _stream| panic!("boom"));
+        let mut client = connect(router);
+        client.write_all(b"GET /boom HTTP/1.1\r\nX-Request-Id: trace-1\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 500 Internal Server Error"), "{}", resp);
+        assert!(resp.contains("X-Request-Id: trace-1\r\n"), "{}", resp);
+    }
+
+    #[test]
+    fn router_swap_keeps_in_flight_requests_on_old_router() {
+        use std::sync::mpsc;
+        let (entered_tx, entered_rx) = mpsc::channel::<()>();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = std::sync::Mutex::new(release_rx);
+        let mut old = Router::new();
+        old.add_route(Method::Get, "/version", move |_req, stream| {
+            entered_tx.send(()).unwrap();
+            release_rx.lock().unwrap().recv().unwrap();
+            write_response(stream, 200, "OK", "text/plain", b"v1")
+        });
+        let server = Server::new(old);
+        let routes = server.router_handle();
+        let listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.to_string();
+        std::thread::spawn(move || server.serve_on(vec![listener]));
+
+        let url = format!("http://{}/version", addr);
+        let in_flight = std::thread::spawn({
+            let url = url.clone();
+            move || Client::new().get(&url).unwrap().text()
+        });
+        entered_rx.recv().unwrap();
+
+        let mut new = Router::new();
+        new.add_route(Method::Get, "/version", |_req, stream| write_response(stream, 200, "OK", "text/plain", b"v2"));
+        let previous = routes.swap(new);
+        assert_eq!(Client::new().get(&url).unwrap().text(), "v2");
+        assert!(std::sync::Arc::strong_count(&previous) > 1, "the in-flight request still holds the old router");
+
+        release_tx.send(()).unwrap();
+        assert_eq!(in_flight.join().unwrap(), "v1");
+        assert_eq!(Client::new().get(&url).unwrap().text(), "v2");
+    }
+
+    #[test]
+    fn server_answers_503_when_route_deadline_passes() {
+        use std::io::{Read, Write};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+        let metrics = Arc::new(Metrics::new());
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut router = Router::new();
+        router
+            .add_route(Method::Get, "/slow", move |req, stream| {
+                tx.send(req.remaining()).unwrap();
+                std::thread::sleep(Duration::from_millis(600));
+                tx.send(req.remaining()).unwrap();
+                write_response(stream, 200, "OK", "text/plain", b"too late")
+            })
+            .deadline(Duration::from_millis(100));
+        let mut client = connect_server(Server::new(router).metrics(Arc::clone(&metrics)));
+
+        let sent = Instant::now();
+        client.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(sent.elapsed() < Duration::from_millis(500), "{:?}", sent.elapsed());
+        assert!(resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", resp);
+        assert!(resp.contains("X-Request-Id: "));
+        assert!(resp.ends_with("deadline exceeded\"}"), "{}", resp);
+
+        assert!(rx.recv().unwrap().is_some_and(|left| left > Duration::ZERO && left <= Duration::from_millis(100)));
+        assert_eq!(rx.recv().unwrap(), Some(Duration::ZERO));
+        let mut text = String::new();
+        for _ in 0..100 {
+            text = metrics.render();
+            if text.contains("http_requests_in_flight 0\n") {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(text.contains("http_deadlines_exceeded_total{method=\"GET\",route=\"/slow\"} 1\n"), "{}", text);
+        assert!(text.contains("http_requests_total{method=\"GET\",route=\"/slow\",status=\"503\"} 1\n"), "{}", text);
+    }
+
+    #[test]
+    fn deadline_passing_during_continued_upload_answers_503() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::time::Duration;
+        let mut router = echo_router(1024);
+        router.add_route(Method::Post, "/upload", |req, stream| write_response(stream, 200, "OK", "text/plain", &req.body)).deadline(Duration::from_millis(100));
+        let mut client = connect(router);
+        client.write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 100 Continue\r\n");
+        reader.read_line(&mut line).unwrap();
+
+        // The body never arrives.
+        let mut resp = String::new();
+        reader.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", resp);
+        assert!(resp.ends_with("deadline exceeded\"}"), "{}", resp);
+    }
 }
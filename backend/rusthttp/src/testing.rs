@@ -0,0 +1,24 @@
+//! Helpers shared by the unit tests of several modules.
+
+use crate::{Method, Router, Server, server, write_response};
+
+/// Serve one connection from a background thread and return the client side.
+pub(crate) fn connect(router: Router) -> std::net::TcpStream {
+    connect_server(Server::new(router))
+}
+
+pub(crate) fn connect_server(server: Server) -> std::net::TcpStream {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        server::serve_tcp(stream, &server).unwrap();
+    });
+    std::net::TcpStream::connect(addr).unwrap()
+}
+
+pub(crate) fn echo_router(limit: u64) -> Router {
+    let mut router = Router::new();
+    router.add_route(Method::Post, "/echo", |req, stream| write_response(stream, 200, "OK", "text/plain", &req.body)).body_limit(limit);
+    router
+}
@@ -0,0 +1,251 @@
+//! HTTPS support, enabled with the `tls` cargo feature.
+//!
+//! Certificates are loaded from PEM files and can be reloaded while the server runs; new
+//! handshakes use the new certificate and existing connections are unaffected.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::request::TlsInfo;
+
+/// Serves whichever certificate was most recently loaded.
+struct ReloadableCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl fmt::Debug for ReloadableCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadableCert").finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.current.read().unwrap()))
+    }
+}
+
+/// TLS settings for [`Server::tls`](crate::Server::tls).
+///
+/// Cloning is cheap and clones share the loaded certificate, so a clone kept outside the server
+/// can call [`reload`](Self::reload).
+#[derive(Clone)]
+pub struct TlsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    cert: Arc<ReloadableCert>,
+    config: Arc<ServerConfig>,
+    handshake_timeout: Duration,
+}
+
+impl TlsConfig {
+    /// Load a certificate chain and private key from PEM files.
+    ///
+    /// The chain starts with the server's own certificate; the key may be PKCS#8, PKCS#1 or SEC1,
+    /// and must be the one the certificate was issued for.
+    pub fn from_pem_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> io::Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref().to_path_buf(), key_path.as_ref().to_path_buf());
+        let cert = Arc::new(ReloadableCert { current: RwLock::new(load_certified_key(&cert_path, &key_path)?) });
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(&cert) as Arc<dyn ResolvesServerCert>);
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Self { cert_path, key_path, cert, config: Arc::new(config), handshake_timeout: Duration::from_secs(10) })
+    }
+
+    /// Longest the handshake may wait for a client to send or receive data before the connection
+    /// is dropped (10 seconds by default), so silent clients do not hold a thread each.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Re-read the certificate and key files.
+    ///
+    /// On error the previously loaded certificate stays in use.
+    pub fn reload(&self) -> io::Result<()> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.cert.current.write().unwrap() = key;
+        Ok(())
+    }
+
+    /// Reload automatically whenever either file's modification time changes, checking every
+    /// `interval` from a background thread.
+    pub fn watch(&self, interval: Duration) {
+        let tls = self.clone();
+        thread::spawn(move || {
+            let mut last = tls.modified();
+            loop {
+                thread::sleep(interval);
+                let now = tls.modified();
+                if now != last {
+                    match tls.reload() {
+                        Ok(()) => println!("Reloaded TLS certificate from {}", tls.cert_path.display()),
+                        Err(e) => eprintln!("failed to reload TLS certificate: {}", e),
+                    }
+                    last = now;
+                }
+            }
+        });
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let mtime = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        (mtime(&self.cert_path), mtime(&self.key_path))
+    }
+
+    /// Perform the server side of the handshake on `tcp`.
+    pub(crate) fn accept(&self, tcp: TcpStream) -> io::Result<TlsStream> {
+        let conn = ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        let previous = (tcp.read_timeout()?, tcp.write_timeout()?);
+        tcp.set_read_timeout(Some(self.handshake_timeout))?;
+        tcp.set_write_timeout(Some(self.handshake_timeout))?;
+        let mut stream = StreamOwned::new(conn, tcp);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        stream.sock.set_read_timeout(previous.0)?;
+        stream.sock.set_write_timeout(previous.1)?;
+        Ok(TlsStream(stream))
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> io::Result<Arc<CertifiedKey>> {
+    let invalid = |path: &Path, e: &dyn fmt::Display| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+
+    let certs = CertificateDer::pem_file_iter(cert_path).map_err(|e| invalid(cert_path, &e))?.collect::<Result<Vec<_>, _>>().map_err(|e| invalid(cert_path, &e))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_path, &"no certificates found"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| invalid(key_path, &e))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(|e| invalid(key_path, &e))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    // Otherwise a mismatched pair would only show up as failing handshakes.
+    certified.keys_match().map_err(|e| invalid(key_path, &format!("{} for {}", e, cert_path.display())))?;
+    Ok(Arc::new(certified))
+}
+
+/// A server-side TLS connection over TCP.
+pub(crate) struct TlsStream(StreamOwned<ServerConnection, TcpStream>);
+
+impl TlsStream {
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        &self.0.sock
+    }
+
+    /// What was negotiated during the handshake.
+    pub(crate) fn info(&self) -> TlsInfo {
+        let conn = &self.0.conn;
+        let version = match conn.protocol_version() {
+            Some(rustls::ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+            Some(rustls::ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+            Some(other) => format!("{:?}", other),
+            None => String::new(),
+        };
+        TlsInfo {
+            version,
+            cipher_suite: conn.negotiated_cipher_suite().and_then(|s| s.suite().as_str()).map(str::to_string),
+            alpn_protocol: conn.alpn_protocol().map(|p| String::from_utf8_lossy(p).into_owned()),
+            server_name: conn.server_name().map(str::to_string),
+        }
+    }
+
+    /// Send `close_notify` so the peer can tell a complete response from a truncated one.
+    pub(crate) fn close(&mut self) -> io::Result<()> {
+        self.0.conn.send_close_notify();
+        self.0.flush()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Method, Router, Server, server, write_response};
+
+    #[test]
+    fn tls_server_reports_negotiated_protocol() {
+        use std::io::{Read, Write};
+        use std::sync::Arc;
+        use rustls::pki_types::{CertificateDer, ServerName, pem::PemObject};
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/certs");
+        let cert = format!("{}/localhost-cert.pem", dir);
+        let tls = TlsConfig::from_pem_files(&cert, format!("{}/localhost-key.pem", dir)).unwrap();
+        tls.reload().unwrap();
+
+        let mut router = Router::new();
+        router.add_route(Method::Get, "/tls", |req, stream| {
+            let info = req.connection.tls.clone().unwrap_or_default();
+            let body = format!("{} {:?} {:?}", info.version, info.alpn_protocol, info.server_name);
+            write_response(stream, 200, "OK", "text/plain", body.as_bytes())
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            server::serve_tcp(stream, &Server::new(router).tls(tls)).unwrap();
+        });
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file(&cert).unwrap()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider).with_safe_default_protocol_versions().unwrap().with_root_certificates(roots).with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let conn = rustls::ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut client = rustls::StreamOwned::new(conn, std::net::TcpStream::connect(addr).unwrap());
+
+        client.write_all(b"GET /tls HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+        assert!(resp.ends_with("TLSv1.3 Some(\"http/1.1\") Some(\"localhost\")"), "{}", resp);
+    }
+
+    #[test]
+    fn tls_rejects_mismatched_keys_and_silent_handshakes() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/certs");
+        let err = TlsConfig::from_pem_files(format!("{}/localhost-cert.pem", dir), format!("{}/other-key.pem", dir)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("other-key.pem"), "{}", err);
+
+        let tls = TlsConfig::from_pem_files(format!("{}/localhost-cert.pem", dir), format!("{}/localhost-key.pem", dir)).unwrap().handshake_timeout(Duration::from_millis(100));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let _silent = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (tcp, _) = listener.accept().unwrap();
+        let started = std::time::Instant::now();
+        assert!(tls.accept(tcp).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    }
+}
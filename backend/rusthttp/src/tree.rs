@@ -0,0 +1,102 @@
+//! The per-method route tree behind [`Router`](crate::Router).
+//!
+//! Each node is one path segment. At every level a static segment is tried before a `:param`,
+//! and a `:param` before a `*wildcard`, so the most specific route wins whatever order routes
+//! were added in. Lookup walks the path once, only backing up when a more specific branch turns
+//! out to be a dead end further down.
+
+use std::collections::HashMap;
+
+/// A node of the tree: the routes reachable from one position in the path.
+#[derive(Default)]
+pub(crate) struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    /// Matches one or more remaining segments.
+    wildcard: Option<(String, usize)>,
+    /// Route ending exactly here.
+    route: Option<usize>,
+}
+
+enum Segment<'p> {
+    Static(&'p str),
+    Param(&'p str),
+    Wildcard(&'p str),
+}
+
+fn parse_segment(segment: &str) -> Segment<'_> {
+    if let Some(name) = segment.strip_prefix(':') {
+        Segment::Param(name)
+    } else if let Some(name) = segment.strip_prefix('*') {
+        Segment::Wildcard(name)
+    } else {
+        Segment::Static(segment)
+    }
+}
+
+/// The segments of `path` after its leading `/`, or `None` if it has none.
+pub(crate) fn segments(path: &str) -> Option<Vec<&str>> {
+    Some(path.strip_prefix('/')?.split('/').collect())
+}
+
+impl Node {
+    /// Add the route at `index` under `pattern`, describing any conflict with an earlier route.
+    pub(crate) fn insert(&mut self, pattern: &str, index: usize) -> Result<(), String> {
+        let segments = segments(pattern).ok_or("pattern must start with `/`")?;
+        let mut node = self;
+        for (i, segment) in segments.iter().enumerate() {
+            node = match parse_segment(segment) {
+                Segment::Static(name) => node.statics.entry(name.to_string()).or_default(),
+                Segment::Param(name) => {
+                    if name.is_empty() {
+                        return Err("parameters need a name, e.g. `:id`".to_string());
+                    }
+                    let (existing, child) = node.param.get_or_insert_with(|| (name.to_string(), Box::default()));
+                    if existing != name {
+                        return Err(format!("`:{}` conflicts with `:{}` at the same position", name, existing));
+                    }
+                    child
+                }
+                Segment::Wildcard(name) => {
+                    if i + 1 != segments.len() {
+                        return Err("a wildcard must be the last segment".to_string());
+                    }
+                    if node.wildcard.is_some() {
+                        return Err("an existing route has the same wildcard".to_string());
+                    }
+                    node.wildcard = Some((name.to_string(), index));
+                    return Ok(());
+                }
+            };
+        }
+        if node.route.is_some() {
+            return Err("an existing route has the same path".to_string());
+        }
+        node.route = Some(index);
+        Ok(())
+    }
+
+    /// Find the most specific route for `segments`, recording captured parameters.
+    pub(crate) fn find(&self, segments: &[&str], params: &mut Vec<(String, String)>) -> Option<usize> {
+        let Some((segment, rest)) = segments.split_first() else {
+            return self.route;
+        };
+        if let Some(found) = self.statics.get(*segment).and_then(|child| child.find(rest, params)) {
+            return Some(found);
+        }
+        if let Some((name, child)) = &self.param
+            && !segment.is_empty()
+        {
+            params.push((name.clone(), segment.to_string()));
+            if let Some(found) = child.find(rest, params) {
+                return Some(found);
+            }
+            params.pop();
+        }
+        let (name, index) = self.wildcard.as_ref()?;
+        if !name.is_empty() {
+            params.push((name.clone(), segments.join("/")));
+        }
+        Some(*index)
+    }
+}
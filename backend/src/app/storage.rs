@@ -31,11 +31,11 @@ pub fn save_notes(path: &Path, notes: &[Note]) -> io::Result<()> {
     if let Some(dir) = path.parent() {
         fs::create_dir_all(dir)?;
     }
-    let json = serde_json::to_string_pretty(notes).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let json = serde_json::to_string_pretty(notes).map_err(io::Error::other)?;
     fs::write(path, json.as_bytes())
 }
 
-pub fn save_user(path: &Path, user: &String, password_hash: &String) -> io::Result<()> {
+pub fn save_user(path: &Path, user: &str, password_hash: &str) -> io::Result<()> {
     if let Some(dir) = path.parent() {
         fs::create_dir_all(dir)?;
     }
@@ -57,7 +57,7 @@ pub fn save_user(path: &Path, user: &String, password_hash: &String) -> io::Resu
     let arr = users.as_array_mut().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "users.json must be a JSON array"))?;
 
     // Reject duplicate usernames.
-    let exists = arr.iter().any(|u| u["username"].as_str() == Some(user.as_str()));
+    let exists = arr.iter().any(|u| u["username"].as_str() == Some(user));
     if exists {
         return Err(io::Error::new(io::ErrorKind::AlreadyExists, "username already exists"));
     }
@@ -69,11 +69,11 @@ pub fn save_user(path: &Path, user: &String, password_hash: &String) -> io::Resu
     }));
 
     // Write back.
-    let out = serde_json::to_string_pretty(&users).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let out = serde_json::to_string_pretty(&users).map_err(io::Error::other)?;
     fs::write(path, out.as_bytes())
 }
 
-pub fn check_user(path: &Path, user: &String, password: &String) -> CheckUserReturn {
+pub fn check_user(path: &Path, user: &str, password: &str) -> CheckUserReturn {
     let text = match fs::read_to_string(path) {
         Ok(t) => t,
         Err(e) if e.kind() == io::ErrorKind::NotFound => return CheckUserReturn { exists: false, correct_password: false, session_token: String::new() },
@@ -93,8 +93,8 @@ pub fn check_user(path: &Path, user: &String, password: &String) -> CheckUserRet
 
     // if password matches, return true
     for u in arr {
-        if u["username"].as_str() == Some(user.as_str()) {
-            let ok = bcrypt::verify(password.as_str(), u["password_hash"].as_str().unwrap_or("")).unwrap_or(false);
+        if u["username"].as_str() == Some(user) {
+            let ok = bcrypt::verify(password, u["password_hash"].as_str().unwrap_or("")).unwrap_or(false);
             return CheckUserReturn { exists: true, correct_password: ok, session_token: String::new() };
         }
     }
@@ -117,6 +117,6 @@ pub fn save_sessions(path: &Path, sessions: &[Session]) -> io::Result<()> {
     if let Some(dir) = path.parent() {
         fs::create_dir_all(dir)?;
     }
-    let json = serde_json::to_string_pretty(sessions).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let json = serde_json::to_string_pretty(sessions).map_err(io::Error::other)?;
     fs::write(path, json.as_bytes())
 }
\ No newline at end of file
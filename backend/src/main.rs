@@ -1,16 +1,22 @@
 use serde::Deserialize;
 use std::{cmp::Ordering, path::Path};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 mod app;
 mod util;
 
 use crate::app::*;
-use rusthttp::{Request, Router, Method, write_response, serve};
+use rusthttp::{AccessLog, ConditionalGet, Csrf, HttpError, Listener, LogFormat, Metrics, Proxy, RateLimiter, Recorder, Request, ResponseCache, Router, Method, RotatingFile, SecurityHeaders, Server, TrustedProxies, write_response, write_response_with_headers};
+use rusthttp::conditional::{self, Precondition};
+use rusthttp::openapi::OpenApi;
 use crate::util::*;
-use bcrypt;
 use rand::{RngCore, rngs::OsRng};
 
+/// How long a request may take before the client is answered with 503, for routes that hash
+/// passwords or write the data files.
+const HANDLER_DEADLINE: Duration = Duration::from_secs(10);
+
 #[derive(Deserialize)]
 struct NoteCreate {
     content: Option<String>,
@@ -46,8 +52,30 @@ fn get_bearer_token(req: &Request) -> Option<String> {
     h.strip_prefix("Bearer ").or_else(|| h.strip_prefix("bearer ")).map(|s| s.to_string())
 }
 
+/// A string field of a JSON request body, for rate-limit keys.
+fn body_field(req: &Request, field: &str) -> Option<String> {
+    let value = serde_json::from_slice::<serde_json::Value>(&req.body).ok()?;
+    value.get(field)?.as_str().map(|s| s.to_string())
+}
+
+/// ETag and Last-Modified of a note; every edit bumps `updated_ms`, so both change with it.
+fn note_validators(note: &Note) -> (String, SystemTime) {
+    (format!("\"{:x}-{}\"", note.id, note.updated_ms), UNIX_EPOCH + Duration::from_millis(note.updated_ms as u64))
+}
+
+/// Client address for log lines, as resolved by the server.
+fn client_addr(req: &Request) -> String {
+    req.client_ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string())
+}
+
 fn main() -> std::io::Result<()> {
-    let addr = "127.0.0.1:8080";
+    // SAFETY: called once, first thing in `main`, before any thread starts.
+    let mut listeners = unsafe { Listener::from_systemd() }?;
+    // Comma-separated addresses or CIDR ranges of reverse proxies allowed to set X-Forwarded-For.
+    let trusted_proxies = match std::env::var("TRUSTED_PROXIES") {
+        Ok(list) => TrustedProxies::parse(&list.split(',').collect::<Vec<_>>())?,
+        Err(_) => TrustedProxies::none(),
+    };
     let data_path = notes_path();
     let initial_notes = match load_notes(&data_path) {
         Ok(notes) => notes,
@@ -69,21 +97,36 @@ fn main() -> std::io::Result<()> {
     let sessions: Arc<Mutex<Vec<Session>>> = Arc::new(Mutex::new(initial_sessions));
 
     let mut router = Router::new();
+    // Opt-in request recording for reproducing bugs; replay with rusthttp's `replay` example.
+    // Credentials in JSON bodies are redacted, and only our user can read what remains.
+    if let Ok(path) = std::env::var("RECORD_REQUESTS") {
+        router.wrap(Recorder::new(RotatingFile::with_mode(path, 50 * 1024 * 1024, 2, 0o600)?).redact_body(&["password", "session_token"]));
+    }
+    // The frontend is served from port 3000 and only calls the API with fetch.
+    router.wrap(SecurityHeaders::new().content_security_policy("default-src 'none'; frame-ancestors 'none'"));
+    router.wrap(Csrf::new().trusted_origin("http://localhost:3000"));
 
     let sessions_for_get_notes = Arc::clone(&sessions);
 
-    router.add_route(Method::Get, "/health", |_req, stream| write_response(stream, 200, "OK", "text/plain", b"ok"));
+    router.add_route(Method::Get, "/health", |_req, stream| write_response(stream, 200, "OK", "text/plain", b"ok")).summary("Liveness check").response(200, "The server is up", None);
+
+    let metrics = Arc::new(Metrics::new());
+    router.add_route(Method::Get, "/metrics", metrics.handler()).summary("Prometheus metrics").response(200, "Metrics in the Prometheus text format", None);
+
+    // Rendered note lists and notes, per session token. Writes invalidate everything under
+    // /api/notes; an expired session can still read its cached list for up to the TTL.
+    let notes_cache = Arc::new(ResponseCache::new(8 * 1024 * 1024).ttl(Duration::from_secs(10)).vary("authorization"));
 
     let notes_list = Arc::clone(&notes);
     router.add_route(Method::Get, "/api/notes", move |req, stream| {
-        let token = match get_bearer_token(&req) {
+        let token = match get_bearer_token(req) {
             Some(t) => t,
-            None => return write_response(stream, 401, "Unauthorized", "application/json", b"{\"error\":\"missing authorization header\"}"),
+            None => return req.send_error(stream, HttpError::new(401, "missing authorization header")),
         };
 
         let session_check = check_session_token(&token, &sessions_for_get_notes);
         if !session_check.valid {
-            return write_response(stream, 401, "Unauthorized", "application/json", b"{\"error\":\"invalid session token\"}");
+            return req.send_error(stream, HttpError::new(401, "invalid session token"));
         }
 
         let notes = notes_list.lock().unwrap();
@@ -96,16 +139,34 @@ fn main() -> std::io::Result<()> {
 
         let body = serde_json::to_string(&ordered).unwrap_or_else(|_| "[]".to_string());
         write_response(stream, 200, "OK", "application/json", body.as_bytes())
-    });
+    })
+    .name("notes")
+    .summary("List the signed-in user's notes, pinned first")
+    .response(200, "The notes", Some(&array_of("Note")))
+    .response(401, "Missing or invalid session token", Some(&schema_ref("Error")))
+    .auth("session")
+    .wrap(ConditionalGet::new())
+    .wrap(Arc::clone(&notes_cache));
 
     let notes_create = Arc::clone(&notes);
+    let cache_create = Arc::clone(&notes_cache);
     let data_path_create = data_path.clone();
 
     let sessions_for_post_notes = Arc::clone(&sessions);
+    // Per user for a valid session token, otherwise per client IP: keying by a token nobody
+    // checked would let a client pick a fresh bucket for every request.
+    let sessions_for_notes_limit = Arc::clone(&sessions);
+    let notes_limit = RateLimiter::per_minute(60).key_by(move |req| {
+        let session = body_field(req, "session_token").map(|token| check_session_token(&token, &sessions_for_notes_limit));
+        match session {
+            Some(session) if session.valid => Some(format!("user:{}", session.username)),
+            _ => req.client_ip.map(|ip| format!("ip:{}", ip)),
+        }
+    });
     router.add_route(Method::Post, "/api/notes", move |req, stream| {
         let payload = match serde_json::from_slice::<NoteCreate>(&req.body) {
             Ok(payload) => payload,
-            Err(_) => return write_response(stream, 400, "Bad Request", "application/json", b"{\"error\":\"invalid json\"}"),
+            Err(_) => return req.send_error(stream, HttpError::new(400, "invalid json")),
         };
 
         let content = payload.content.unwrap_or_default();
@@ -117,7 +178,7 @@ fn main() -> std::io::Result<()> {
 
         let session_check = check_session_token(&payload.session_token, &sessions_for_post_notes);
         if !session_check.valid {
-            return write_response(stream, 401, "Unauthorized", "application/json", b"{\"error\":\"invalid session token\"}");
+            return req.send_error(stream, HttpError::new(401, "invalid session token"));
         }
 
         let note = Note { username: session_check.username, id, created_ms: t, updated_ms: t, pinned, tags, content, changes: Vec::new() };
@@ -125,41 +186,63 @@ fn main() -> std::io::Result<()> {
         {
             let mut notes = notes_create.lock().unwrap();
             notes.push(note.clone());
+            cache_create.invalidate("/api/notes");
             if let Err(e) = save_notes(&data_path_create, &notes) {
-                eprintln!("failed to save notes: {}", e);
+                eprintln!("failed to save notes (request {}): {}", req.request_id, e);
             }
         }
 
         let resp = serde_json::to_string(&note).unwrap_or_else(|_| "{}".to_string());
-        write_response(stream, 201, "Created", "application/json", resp.as_bytes())
-    });
+        let location = req.url_for("note", &[("id", &note.id.to_string())])?;
+        write_response_with_headers(stream, 201, "Created", "application/json", &[("Location", &location)], resp.as_bytes())
+    })
+    .accepts(&["application/json"])
+    .summary("Create a note; the session token goes in the body")
+    .request_schema(&schema_ref("NoteCreate"))
+    .response(201, "The created note, with its URL in `Location`", Some(&schema_ref("Note")))
+    .response(400, "Invalid JSON", Some(&schema_ref("Error")))
+    .response(401, "Invalid session token", Some(&schema_ref("Error")))
+    .response(429, "Too many requests", Some(&schema_ref("Error")))
+    .deadline(HANDLER_DEADLINE)
+    .wrap(notes_limit);
 
     let notes_get_one = Arc::clone(&notes);
-    router.add_prefix_route(Method::Get, "/api/notes/", move |req, stream| {
-        let id = match parse_note_id(&req.path) {
+    router.add_route(Method::Get, "/api/notes/:id", move |req, stream| {
+        let id = match note_id(req) {
             Some(id) => id,
-            None => return write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}"),
+            None => return req.send_error(stream, HttpError::new(404, "not found")),
         };
 
         let notes = notes_get_one.lock().unwrap();
         if let Some(note) = notes.iter().find(|n| n.id == id) {
             let resp = serde_json::to_string(note).unwrap_or_else(|_| "{}".to_string());
-            write_response(stream, 200, "OK", "application/json", resp.as_bytes())
+            let (etag, modified) = note_validators(note);
+            let history = format!("<{}>; rel=\"history\"", req.url_for("note_changes", &[("id", &id.to_string())])?);
+            let headers = [("ETag", etag.as_str()), ("Last-Modified", &rusthttp::date::format_http_date(modified)), ("Link", &history)];
+            write_response_with_headers(stream, 200, "OK", "application/json", &headers, resp.as_bytes())
         } else {
-            write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}")
+            req.send_error(stream, HttpError::new(404, "not found"))
         }
-    });
+    })
+    .name("note")
+    .summary("Fetch a note; its change history is linked with `rel=\"history\"`")
+    .response(200, "The note", Some(&schema_ref("Note")))
+    .response(304, "Not modified since the `If-None-Match` ETag", None)
+    .response(404, "No such note", Some(&schema_ref("Error")))
+    .wrap(ConditionalGet::new())
+    .wrap(Arc::clone(&notes_cache));
 
     let notes_patch = Arc::clone(&notes);
+    let cache_patch = Arc::clone(&notes_cache);
     let data_path_patch = data_path.clone();
-    router.add_prefix_route(Method::Patch, "/api/notes/", move |req, stream| {
-        let id = match parse_note_id(&req.path) {
+    router.add_route(Method::Patch, "/api/notes/:id", move |req, stream| {
+        let id = match note_id(req) {
             Some(id) => id,
-            None => return write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}"),
+            None => return req.send_error(stream, HttpError::new(404, "not found")),
         };
         let patch = match serde_json::from_slice::<NotePatch>(&req.body) {
             Ok(patch) => patch,
-            Err(_) => return write_response(stream, 400, "Bad Request", "application/json", b"{\"error\":\"invalid json\"}"),
+            Err(_) => return req.send_error(stream, HttpError::new(400, "invalid json")),
         };
 
         let before_pin_change = {
@@ -178,6 +261,11 @@ fn main() -> std::io::Result<()> {
         let mut notes = notes_patch.lock().unwrap();
         let note_index = notes.iter().position(|n| n.id == id);
         if let Some(index) = note_index {
+            // Clients send the ETag they last saw in If-Match so concurrent edits are not lost.
+            let (etag, modified) = note_validators(&notes[index]);
+            if conditional::evaluate(req, Some(&etag), Some(modified)) == Precondition::Failed {
+                return req.send_error(stream, HttpError::new(412, "note was changed by another request"));
+            }
             let note = &mut notes[index];
             if let Some(content) = patch.content {
                 note.content = content;
@@ -195,49 +283,73 @@ fn main() -> std::io::Result<()> {
                 tag_change: TagChange { before: before_tag_change.unwrap_or_else(|| note.tags.clone()), after: note.tags.clone() },
                 content_change: ContentChange { before: before_content_change.unwrap_or_else(|| note.content.clone()), after: note.content.clone() },
             });
+            cache_patch.invalidate("/api/notes");
             if let Err(e) = save_notes(&data_path_patch, &notes) {
-                eprintln!("failed to save notes: {}", e);
+                eprintln!("failed to save notes (request {}): {}", req.request_id, e);
             }
             let resp = serde_json::to_string(&notes[index]).unwrap_or_else(|_| "{}".to_string());
-            write_response(stream, 200, "OK", "application/json", resp.as_bytes())
+            let (etag, _) = note_validators(&notes[index]);
+            write_response_with_headers(stream, 200, "OK", "application/json", &[("ETag", &etag)], resp.as_bytes())
         } else {
-            write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}")
+            req.send_error(stream, HttpError::new(404, "not found"))
         }
-    });
+    })
+    .accepts(&["application/json"])
+    .summary("Update a note's content, pin or tags")
+    .request_schema(&schema_ref("NotePatch"))
+    .response(200, "The updated note", Some(&schema_ref("Note")))
+    .response(400, "Invalid JSON", Some(&schema_ref("Error")))
+    .response(404, "No such note", Some(&schema_ref("Error")))
+    .response(412, "The note changed since the `If-Match` ETag", Some(&schema_ref("Error")))
+    .deadline(HANDLER_DEADLINE);
 
     let notes_delete = Arc::clone(&notes);
+    let cache_delete = Arc::clone(&notes_cache);
     let data_path_delete = data_path.clone();
-    router.add_prefix_route(Method::Delete, "/api/notes/", move |req, stream| {
-        let id = match parse_note_id(&req.path) {
+    router.add_route(Method::Delete, "/api/notes/:id", move |req, stream| {
+        let id = match note_id(req) {
             Some(id) => id,
-            None => return write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}"),
+            None => return req.send_error(stream, HttpError::new(404, "not found")),
         };
 
         let mut notes = notes_delete.lock().unwrap();
-        let before = notes.len();
-        notes.retain(|n| n.id != id);
-        if notes.len() == before {
-            return write_response(stream, 404, "Not Found", "application/json", b"{\"error\":\"not found\"}");
+        let Some(index) = notes.iter().position(|n| n.id == id) else {
+            return req.send_error(stream, HttpError::new(404, "not found"));
+        };
+        let (etag, modified) = note_validators(&notes[index]);
+        if conditional::evaluate(req, Some(&etag), Some(modified)) == Precondition::Failed {
+            return req.send_error(stream, HttpError::new(412, "note was changed by another request"));
         }
+        notes.remove(index);
+        cache_delete.invalidate("/api/notes");
         if let Err(e) = save_notes(&data_path_delete, &notes) {
-            eprintln!("failed to save notes: {}", e);
+            eprintln!("failed to save notes (request {}): {}", req.request_id, e);
         }
         write_response(stream, 204, "No Content", "text/plain", b"")
-    });
+    })
+    .summary("Delete a note")
+    .response(204, "Deleted", None)
+    .response(404, "No such note", Some(&schema_ref("Error")))
+    .response(412, "The note changed since the `If-Match` ETag", Some(&schema_ref("Error")));
 
     let notes_changes = Arc::clone(&notes);
-    router.add_prefix_route(Method::Get, "/api/notes-changes/", move |req, stream| {
-        let id = match parse_note_changes_id(&req.path) {
+    router.add_route(Method::Get, "/api/notes-changes/:id", move |req, stream| {
+        let id = match note_id(req) {
             Some(id) => id,
-            None => return write_response(stream, 404, "Not Found", "text/plain", b"invalid note id"),
+            None => return req.send_error(stream, HttpError::new(404, "invalid note id")),
         };
 
         let notes = notes_changes.lock().unwrap();
         let note = match notes.iter().find(|n| n.id == id) {
             Some(n) => n,
-            None => return write_response(stream, 404, "Not Found", "text/plain", b"note not found"),
+            None => return req.send_error(stream, HttpError::new(404, "note not found")),
         };
 
+        if req.response_type.as_deref() == Some("application/json") {
+            let body = serde_json::to_string(&note.changes).unwrap_or_else(|_| "[]".to_string());
+            return write_response(stream, 200, "OK", "application/json", body.as_bytes());
+        }
+
         let mut s = String::new();
 
         for c in note.changes.iter() {
@@ -254,39 +366,64 @@ fn main() -> std::io::Result<()> {
         }
 
         write_response(stream, 200, "OK", "text/plain", s.as_bytes())
-    });
+    })
+    .name("note_changes")
+    .produces(&["text/plain", "application/json"])
+    .summary("A note's change history, as text or JSON")
+    .response(200, "The changes, oldest first", Some(&array_of("Change")))
+    .response(404, "No such note", Some(&schema_ref("Error")));
+
+    // Sign-up and sign-in share a per-IP budget; sign-in is also limited per username to slow
+    // password guessing spread across addresses.
+    let auth_ip_limit = Arc::new(RateLimiter::per_minute(10));
+    let signin_user_limit = RateLimiter::per_minute(5).key_by(|req| body_field(req, "username"));
 
     router.add_route(Method::Post, "/api/signup", move |req, stream| {
         let payload = match serde_json::from_slice::<SignPayload>(&req.body) {
             Ok(payload) => payload,
-            Err(_) => return write_response(stream, 400, "Bad Request", "application/json", b"{\"error\":\"invalid json\"}"),
+            Err(_) => return req.send_error(stream, HttpError::new(400, "invalid json")),
         };
 
         let hashed_password = match bcrypt::hash(&payload.password, bcrypt::DEFAULT_COST) {
             Ok(h) => h,
-            Err(_) => return write_response(stream, 500, "Internal Server Error", "application/json", b"{\"error\":\"hash failed\"}"),
+            Err(_) => return req.send_error(stream, HttpError::new(500, "hash failed")),
         };
 
+        // The client has already been answered with 503; do not create an account it never saw.
+        if req.remaining() == Some(Duration::ZERO) {
+            eprintln!("signup for {:?} abandoned after its deadline (request {})", payload.username, req.request_id);
+            return Ok(());
+        }
+
         let user_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data").join("users.json");
         if let Err(e) = save_user(&user_path, &payload.username, &hashed_password) {
-            eprintln!("failed to save user: {}", e);
-            return write_response(stream, 500, "Internal Server Error", "application/json", b"{\"error\":\"internal server error\"}");
+            eprintln!("failed to save user {:?} from {} (request {}): {}", payload.username, client_addr(req), req.request_id, e);
+            return req.send_error(stream, HttpError::new(500, "internal server error"));
         }
         write_response(stream, 200, "OK", "application/json", b"{\"status\":\"user created\"}")
-    });
+    })
+    .accepts(&["application/json"])
+    .summary("Create an account")
+    .request_schema(&schema_ref("Credentials"))
+    .response(200, "Account created", Some(&schema_ref("Status")))
+    .response(400, "Invalid JSON", Some(&schema_ref("Error")))
+    .response(429, "Too many requests", Some(&schema_ref("Error")))
+    .deadline(HANDLER_DEADLINE)
+    .wrap(Arc::clone(&auth_ip_limit));
 
     let sessions_for_post_signin = Arc::clone(&sessions);
     router.add_route(Method::Post, "/api/signin", move |req, stream| {
         let payload = match serde_json::from_slice::<SignPayload>(&req.body) {
             Ok(payload) => payload,
-            Err(_) => return write_response(stream, 400, "Bad Request", "application/json", b"{\"error\":\"invalid json\"}"),
+            Err(_) => return req.send_error(stream, HttpError::new(400, "invalid json")),
         };
 
         let user_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data").join("users.json");
         let check_user_response = check_user(&user_path, &payload.username, &payload.password);
 
-        if check_user_response.exists == false || check_user_response.correct_password == false {
-            return write_response(stream, 401, "Unauthorized", "application/json", b"{\"error\":\"invalid credentials\"}");
+        if !check_user_response.exists || !check_user_response.correct_password {
+            eprintln!("failed sign-in for {:?} from {} (request {})", payload.username, client_addr(req), req.request_id);
+            return req.send_error(stream, HttpError::new(401, "invalid credentials"));
         }
 
         // 32 random bytes -> hex string token
@@ -308,48 +445,132 @@ fn main() -> std::io::Result<()> {
         .to_string();
 
         if let Err(e) = save_sessions(&sessions_path, &sessions_for_post_signin.lock().unwrap()) {
-            eprintln!("failed to save sessions: {}", e);
+            eprintln!("failed to save sessions (request {}): {}", req.request_id, e);
         }
 
         write_response(stream, 200, "OK", "application/json", body.as_bytes())
-    });
+    })
+    .accepts(&["application/json"])
+    .summary("Sign in, returning a session token valid for an hour")
+    .request_schema(&schema_ref("Credentials"))
+    .response(200, "Signed in", Some(&schema_ref("SignIn")))
+    .response(400, "Invalid JSON", Some(&schema_ref("Error")))
+    .response(401, "Invalid credentials", Some(&schema_ref("Error")))
+    .response(429, "Too many requests", Some(&schema_ref("Error")))
+    .deadline(HANDLER_DEADLINE)
+    .wrap(auth_ip_limit)
+    .wrap(signin_user_limit);
+
+    // Prefixes forwarded to other services during migration, as comma-separated `prefix=host:port` pairs.
+    if let Ok(routes) = std::env::var("PROXY_ROUTES") {
+        for entry in routes.split(',').filter(|e| !e.trim().is_empty()) {
+            let (prefix, upstream) = entry
+                .split_once('=')
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("bad PROXY_ROUTES entry: {}", entry)))?;
+            Proxy::new(upstream.trim()).mount(&mut router, prefix.trim());
+        }
+    }
 
-    serve(addr, router)
-}
+    let openapi_path = std::env::var("OPENAPI_PATH").unwrap_or_else(|_| "/api/openapi.json".to_string());
+    api_description().mount(&mut router, &openapi_path);
 
-fn parse_note_changes_id(path: &str) -> Option<u64> {
-    let prefix = "/api/notes-changes/";
-    if !path.starts_with(prefix) {
-        return None;
-    }
-    let id_str = &path[prefix.len()..];
-    if id_str.is_empty() {
-        return None;
-    }
-    id_str.parse::<u64>().ok()
-}
+    // Access log: JSON lines to a rotating file if ACCESS_LOG is set, otherwise Combined format on stdout.
+    let access_log = match std::env::var("ACCESS_LOG") {
+        Ok(path) => AccessLog::file(LogFormat::Json, RotatingFile::new(path, 10 * 1024 * 1024, 5)?),
+        Err(_) => AccessLog::stdout(LogFormat::Combined),
+    };
 
-fn parse_note_id(path: &str) -> Option<u64> {
-    let prefix = "/api/notes/";
-    if !path.starts_with(prefix) {
-        return None;
+    let server = Server::new(router).trusted_proxies(trusted_proxies).access_log(access_log).metrics(metrics);
+    #[cfg(feature = "tls")]
+    let server = match (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+        (Ok(cert), Ok(key)) => {
+            let tls = rusthttp::TlsConfig::from_pem_files(cert, key)?;
+            tls.watch(std::time::Duration::from_secs(30));
+            server.tls(tls)
+        }
+        _ => server,
+    };
+    // Socket-activated listeners from systemd, else LISTEN: comma-separated `host:port` or
+    // `unix:/path` addresses.
+    if listeners.is_empty() {
+        let addrs = std::env::var("LISTEN").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+        for addr in addrs.split(',').filter(|a| !a.trim().is_empty()) {
+            listeners.push(Listener::bind(addr.trim())?);
+        }
     }
-    let id_str = &path[prefix.len()..];
-    id_str.parse::<u64>().ok()
+    server.serve_on(listeners)
 }
 
-/*
-curl -i http://127.0.0.1:8080/health
-
-curl -i -X POST http://127.0.0.1:8080/api/notes \
-  -H 'Content-Type: application/json' \
-  -d '{"content":"hello from rust","pinned":false,"tags":["rust","notes"]}'
+/// The `:id` of a note route.
+fn note_id(req: &Request) -> Option<u64> {
+    req.param("id")?.parse().ok()
+}
 
-curl -i http://127.0.0.1:8080/api/notes
+/// `{"$ref": ...}` to a schema defined in [`api_description`].
+fn schema_ref(name: &str) -> String {
+    serde_json::json!({ "$ref": format!("#/components/schemas/{}", name) }).to_string()
+}
 
-curl -i -X PATCH http://127.0.0.1:8080/api/notes/{id} \
-  -H 'Content-Type: application/json' \
-  -d '{"content":"updated","pinned":true,"tags":["pinned"]}'
+fn array_of(name: &str) -> String {
+    serde_json::json!({ "type": "array", "items": { "$ref": format!("#/components/schemas/{}", name) } }).to_string()
+}
 
-curl -i -X DELETE http://127.0.0.1:8080/api/notes/{id}
-*/
+/// OpenAPI document metadata and the schemas the routes refer to.
+fn api_description() -> OpenApi {
+    let string_array = serde_json::json!({ "type": "array", "items": { "type": "string" } });
+    let before_after = |schema: &serde_json::Value| serde_json::json!({ "type": "object", "properties": { "before": schema, "after": schema }, "required": ["before", "after"] });
+    let schemas = [
+        ("Error", serde_json::json!({ "type": "object", "properties": { "error": { "type": "string" } }, "required": ["error"] })),
+        ("Status", serde_json::json!({ "type": "object", "properties": { "status": { "type": "string" } }, "required": ["status"] })),
+        (
+            "Note",
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "username": { "type": "string" },
+                    "id": { "type": "string", "description": "64-bit id, as a string" },
+                    "created_ms": { "type": "integer", "description": "Unix time in milliseconds" },
+                    "updated_ms": { "type": "integer", "description": "Unix time in milliseconds" },
+                    "pinned": { "type": "boolean" },
+                    "tags": string_array,
+                    "content": { "type": "string" },
+                    "changes": { "type": "array", "items": { "$ref": "#/components/schemas/Change" } }
+                },
+                "required": ["username", "id", "created_ms", "updated_ms", "pinned", "tags", "content", "changes"]
+            }),
+        ),
+        (
+            "NoteCreate",
+            serde_json::json!({
+                "type": "object",
+                "properties": { "session_token": { "type": "string" }, "content": { "type": "string" }, "pinned": { "type": "boolean" }, "tags": string_array },
+                "required": ["session_token"]
+            }),
+        ),
+        ("NotePatch", serde_json::json!({ "type": "object", "properties": { "content": { "type": "string" }, "pinned": { "type": "boolean" }, "tags": string_array } })),
+        (
+            "Change",
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "change_date_ms": { "type": "integer" },
+                    "pin_change": before_after(&serde_json::json!({ "type": "boolean" })),
+                    "tag_change": before_after(&string_array),
+                    "content_change": before_after(&serde_json::json!({ "type": "string" }))
+                },
+                "required": ["change_date_ms", "pin_change", "tag_change", "content_change"]
+            }),
+        ),
+        ("Credentials", serde_json::json!({ "type": "object", "properties": { "username": { "type": "string" }, "password": { "type": "string" } }, "required": ["username", "password"] })),
+        (
+            "SignIn",
+            serde_json::json!({
+                "type": "object",
+                "properties": { "status": { "type": "string" }, "session_token": { "type": "string" }, "expires_at_ms": { "type": "integer", "description": "Unix time in milliseconds" } },
+                "required": ["status", "session_token", "expires_at_ms"]
+            }),
+        ),
+    ];
+    let api = OpenApi::new("Notes API", env!("CARGO_PKG_VERSION")).security_scheme("session", r#"{"type": "http", "scheme": "bearer", "description": "Session token from /api/signin"}"#);
+    schemas.iter().fold(api, |api, (name, schema)| api.schema(name, &schema.to_string()))
+}